@@ -0,0 +1,115 @@
+use crate::log::{self, Level};
+use crate::thread_pool::ThreadPool;
+use crate::{HttpMethod, HttpResponse, HttpServer, Pools, ShutdownHandle};
+use std::sync::atomic::Ordering;
+use std::net::TcpListener;
+use std::sync::{Arc, Weak};
+use std::thread::{self, JoinHandle};
+
+// 管理端口, 与业务流量隔离, 应只绑定在本机或内网地址
+// GET /routes                          已注册的路由
+// GET /stats                           活动连接数、CONNECT 隧道数、已处理请求数、请求排队总时间(微秒)、线程池排队与执行中的任务数
+// GET /log-level, PUT /log-level?level=warn
+// GET /maintenance, PUT /maintenance?enabled=true   维护模式下业务请求一律返回 503
+// POST /shutdown                       与 HttpServer::shutdown_handle 相同的优雅关闭
+
+// 业务服务器关闭时一起关闭
+pub(crate) struct AdminServer {
+    shutdown: ShutdownHandle,
+    thread: JoinHandle<()>,
+}
+
+impl AdminServer {
+    pub(crate) fn stop(self) {
+        self.shutdown.shutdown();
+        if self.thread.join().is_err() {
+            log::error("admin server panicked");
+        }
+    }
+}
+
+// listener 由 HttpServer::run 绑定, 绑定失败时 run 直接返回 Error::Bind
+pub(crate) fn spawn(listener: TcpListener, server: Arc<HttpServer>, pools: Weak<Pools>) -> AdminServer {
+    let address = listener.local_addr().map(|address| address.to_string()).unwrap_or_default();
+    let mut admin = HttpServer::new(address);
+
+    let target = Arc::clone(&server);
+    admin.add_handler(HttpMethod::GET, "/routes".into(), move |ctx| {
+        let routes = target
+            .handlers
+            .iter()
+            .map(|m| {
+                let methods = m.methods.iter().map(|method| format!(r#""{:?}""#, method)).collect::<Vec<_>>();
+                format!(r#"{{"methods": [{}], "path": "{}"}}"#, methods.join(", "), log::escape(&m.path))
+            })
+            .collect::<Vec<_>>();
+        ctx.set_response(HttpResponse::json(format!("[{}]", routes.join(", "))));
+    });
+
+    let target = Arc::clone(&server);
+    admin.add_handler(HttpMethod::GET, "/stats".into(), move |ctx| {
+        // 关闭过程中线程池已经取回, 这时为 null
+        let pools = pools.upgrade();
+        let io_pool = pools.as_ref().map(|pools| &pools.io);
+        let handler_pool = pools.as_ref().and_then(|pools| pools.handler.as_ref());
+        ctx.set_response(HttpResponse::json(format!(
+            r#"{{"active_connections": {}, "active_tunnels": {}, "requests": {}, "queue_wait_us": {}, "maintenance": {}, "io_pool": {}, "handler_pool": {}}}"#,
+            target.active_connections.load(Ordering::Relaxed),
+            target.active_tunnels.load(Ordering::Relaxed),
+            target.requests.load(Ordering::Relaxed),
+            target.queue_wait_micros.load(Ordering::Relaxed),
+            target.maintenance.load(Ordering::Relaxed),
+            pool_stats(io_pool),
+            pool_stats(handler_pool)
+        )));
+    });
+
+    let handle = server.shutdown_handle();
+    admin.add_handler(HttpMethod::POST, "/shutdown".into(), move |ctx| {
+        log::warn("shutdown requested from admin");
+        handle.shutdown();
+        ctx.set_response(HttpResponse::json(r#"{"shutdown": true}"#.into()).status_code(202));
+    });
+
+    admin.add_handler_for(&[HttpMethod::GET, HttpMethod::PUT], "/log-level".into(), |ctx| {
+        if ctx.request.method == HttpMethod::PUT {
+            let Some(level) = ctx.request.query("level").and_then(Level::parse) else {
+                ctx.set_response(HttpResponse::new(400));
+                return;
+            };
+            log::set_level(level);
+        }
+        ctx.set_response(HttpResponse::json(format!(r#"{{"level": "{}"}}"#, log::level().as_str())));
+    });
+
+    let target = server;
+    admin.add_handler_for(&[HttpMethod::GET, HttpMethod::PUT], "/maintenance".into(), move |ctx| {
+        if ctx.request.method == HttpMethod::PUT {
+            let enabled = match ctx.request.query("enabled") {
+                Some("true") => true,
+                Some("false") => false,
+                _ => {
+                    ctx.set_response(HttpResponse::new(400));
+                    return;
+                }
+            };
+            target.maintenance.store(enabled, Ordering::Relaxed);
+            log::warn(&format!("maintenance mode {}", if enabled { "on" } else { "off" }));
+        }
+        ctx.set_response(HttpResponse::json(format!(
+            r#"{{"enabled": {}}}"#,
+            target.maintenance.load(Ordering::Relaxed)
+        )));
+    });
+
+    let shutdown = admin.shutdown_handle();
+    let thread = thread::spawn(move || admin.serve(listener, None));
+    AdminServer { shutdown, thread }
+}
+
+fn pool_stats(pool: Option<&ThreadPool>) -> String {
+    match pool {
+        Some(pool) => format!(r#"{{"queued": {}, "active": {}}}"#, pool.queued_jobs(), pool.active_jobs()),
+        None => "null".into(),
+    }
+}
@@ -0,0 +1,196 @@
+use crate::{HttpRequest, HttpResponse, Middleware};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::sync::Arc;
+
+// API Key 认证, key 可以放在请求头或 query 参数中
+// let auth = ApiKeyAuth::new().key("k-123", ApiKeyInfo::new("ci".into(), &["deploy"]));
+// server.add_middleware(auth.middleware().path("/api/**".into()));
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiKeyInfo {
+    pub name: String,
+    pub scopes: Vec<String>,
+}
+
+impl ApiKeyInfo {
+    pub fn new(name: String, scopes: &[&str]) -> ApiKeyInfo {
+        ApiKeyInfo {
+            name,
+            scopes: scopes.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
+}
+
+// 可以捕获连接池、计数器等状态
+type KeyLookup = Arc<dyn Fn(&str) -> Option<ApiKeyInfo> + Send + Sync>;
+// 返回 false 时响应 429
+type RateLimiter = Arc<dyn Fn(&ApiKeyInfo, &HttpRequest) -> bool + Send + Sync>;
+
+/// API Key 认证的配置, middleware() 之后由中间件持有
+pub struct ApiKeyAuth {
+    header: String,
+    query_param: Option<String>,
+    keys: HashMap<String, ApiKeyInfo>,
+    lookup: Option<KeyLookup>,
+    rate_limiter: Option<RateLimiter>,
+}
+
+impl Default for ApiKeyAuth {
+    fn default() -> Self {
+        ApiKeyAuth {
+            header: "X-API-Key".into(),
+            query_param: None,
+            keys: HashMap::new(),
+            lookup: None,
+            rate_limiter: None,
+        }
+    }
+}
+
+impl ApiKeyAuth {
+    /// 从 X-API-Key 请求头读取 key, 没有 key
+    pub fn new() -> ApiKeyAuth {
+        ApiKeyAuth::default()
+    }
+    pub fn header(mut self, header: &str) -> Self {
+        self.header = header.to_string();
+        self
+    }
+    /// 默认不从 query 读取, key 容易出现在访问日志里
+    pub fn query_param(mut self, name: &str) -> Self {
+        self.query_param = Some(name.to_string());
+        self
+    }
+    pub fn key(mut self, key: &str, info: ApiKeyInfo) -> Self {
+        self.keys.insert(key.to_string(), info);
+        self
+    }
+    /// 每行: <key> <name> [scope1,scope2], # 开头为注释
+    pub fn load_file(mut self, path: &str) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        for line in content.lines().map(|l| l.trim()) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let (Some(key), Some(name)) = (parts.next(), parts.next()) else {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid api key line: {}", line)));
+            };
+            let scopes = parts
+                .next()
+                .map(|s| s.split(',').filter(|s| !s.is_empty()).collect::<Vec<&str>>())
+                .unwrap_or_default();
+            self.keys.insert(key.to_string(), ApiKeyInfo::new(name.to_string(), &scopes));
+        }
+        Ok(self)
+    }
+    /// 静态表中找不到时再调用, 可以接数据库等外部存储
+    pub fn lookup<F>(mut self, lookup: F) -> Self
+    where
+        F: Fn(&str) -> Option<ApiKeyInfo> + Send + Sync + 'static,
+    {
+        self.lookup = Some(Arc::new(lookup));
+        self
+    }
+    /// 返回 false 时回复 429
+    pub fn rate_limiter<F>(mut self, rate_limiter: F) -> Self
+    where
+        F: Fn(&ApiKeyInfo, &HttpRequest) -> bool + Send + Sync + 'static,
+    {
+        self.rate_limiter = Some(Arc::new(rate_limiter));
+        self
+    }
+
+    /// 没有 key 或 key 无效时回复 401, 被限流时回复 429
+    pub fn middleware(self) -> Middleware {
+        Middleware::new(move |chain, ctx| {
+            let Some(info) = self.key_info(&ctx.request) else {
+                ctx.set_response(HttpResponse::new(401));
+                return;
+            };
+            if self.rate_limiter.as_ref().is_some_and(|allow| !allow(&info, &ctx.request)) {
+                ctx.set_response(HttpResponse::new(429));
+                return;
+            }
+            // handler 中通过 ctx.get::<ApiKeyInfo>() 取得, 不必再查一次
+            ctx.set(info);
+            chain.next(ctx);
+        })
+    }
+
+    fn key_info(&self, request: &HttpRequest) -> Option<ApiKeyInfo> {
+        let key = request
+            .header(&self.header)
+            .or_else(|| request.query(self.query_param.as_ref()?))?;
+        match self.keys.get(key) {
+            Some(info) => Some(info.clone()),
+            None => self.lookup.as_ref()?(key),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HttpMethod, HttpServer, Service};
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn get(target: &str, key: Option<&str>) -> HttpRequest {
+        let mut raw = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n", target);
+        if let Some(key) = key {
+            raw.push_str(&format!("X-API-Key: {}\r\n", key));
+        }
+        raw.push_str("\r\n");
+        HttpRequest::parse(&mut raw.as_bytes(), "127.0.0.1:50000".into()).unwrap()
+    }
+
+    fn lookup(key: &str) -> Option<ApiKeyInfo> {
+        (key == "from-db").then(|| ApiKeyInfo::new("db".into(), &[]))
+    }
+
+    #[test]
+    fn keys_belong_to_their_middleware() {
+        let mut server = HttpServer::new("127.0.0.1:0".into());
+        let deploy = ApiKeyAuth::new().key("k-ci", ApiKeyInfo::new("ci".into(), &["deploy"]));
+        server.add_middleware(deploy.middleware().path("/deploy".into()));
+        let reports = ApiKeyAuth::new().query_param("api_key").lookup(lookup);
+        server.add_middleware(reports.middleware().path("/reports".into()));
+        for path in ["/deploy", "/reports"] {
+            server.add_handler(HttpMethod::GET, path.into(), |ctx| {
+                let name = ctx.get::<ApiKeyInfo>().map(|info| info.name.clone()).unwrap_or_default();
+                ctx.set_response(HttpResponse::bytes("text/plain".into(), name.into_bytes()))
+            });
+        }
+
+        assert_eq!(server.call(get("/deploy", None)).status_code, 401);
+        let deployed = server.call(get("/deploy", Some("k-ci")));
+        assert_eq!(deployed.body.as_deref(), Some(&b"ci"[..]));
+        assert_eq!(server.call(get("/deploy", Some("from-db"))).status_code, 401);
+        assert_eq!(server.call(get("/reports", Some("k-ci"))).status_code, 401);
+        let reported = server.call(get("/reports?api_key=from-db", None));
+        assert_eq!(reported.body.as_deref(), Some(&b"db"[..]));
+    }
+
+    #[test]
+    fn lookup_and_rate_limiter_can_capture_state() {
+        let revoked = HashSet::from(["k-old".to_string()]);
+        let remaining = AtomicUsize::new(2);
+        let auth = ApiKeyAuth::new()
+            .lookup(move |key| (!revoked.contains(key)).then(|| ApiKeyInfo::new(key.into(), &[])))
+            .rate_limiter(move |_, _| remaining.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1)).is_ok());
+        let mut server = HttpServer::new("127.0.0.1:0".into());
+        server.add_middleware(auth.middleware());
+        server.add_handler(HttpMethod::GET, "/".into(), |ctx| ctx.set_response(HttpResponse::new(200)));
+
+        assert_eq!(server.call(get("/", Some("k-old"))).status_code, 401);
+        assert_eq!(server.call(get("/", Some("k-new"))).status_code, 200);
+        assert_eq!(server.call(get("/", Some("k-new"))).status_code, 200);
+        assert_eq!(server.call(get("/", Some("k-new"))).status_code, 429);
+    }
+}
@@ -0,0 +1,81 @@
+use crate::{HttpResponse, Middleware};
+use std::io::{self, Write};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread;
+
+// 把流式响应读入内存, 外层中间件(ETag、压缩、内容替换)就能像普通响应一样处理 response.body;
+// 超过上限时改回流式发送: 先发出已读入的部分, 其余继续从生成函数读取, 内存占用不超过上限
+
+// 生成函数与发送之间最多积压的块数
+const PENDING_CHUNKS: usize = 16;
+
+/// 流式响应不超过 cap 字节时转为普通的 body 响应, 应注册在需要处理响应体的中间件内层(order 更大)
+///
+/// 如 server.add_middleware(etag::middleware().order(0)); server.add_middleware(buffering::middleware(64 * 1024).order(1));
+/// 生成函数在 cap 以内出错时返回 500, 而不是发送到一半中断连接
+pub fn middleware(cap: usize) -> Middleware {
+    Middleware::new(move |chain, ctx| {
+        chain.next(ctx);
+        let Some(response) = ctx.response.as_mut() else {
+            return;
+        };
+        let Some(producer) = response.stream.take().and_then(|stream| stream.take()) else {
+            return;
+        };
+        let (sender, receiver) = mpsc::sync_channel(PENDING_CHUNKS);
+        let producing = thread::spawn(move || producer(&mut ChannelWriter(sender)));
+        let mut buffered = Vec::new();
+        for chunk in receiver.iter() {
+            buffered.extend_from_slice(&chunk);
+            if buffered.len() > cap {
+                *response = pass_through(response, buffered, receiver, producing);
+                return;
+            }
+        }
+        match producing.join() {
+            Ok(Ok(())) => *response = response.clone().body(buffered),
+            Ok(Err(e)) => {
+                crate::log::error(&format!("stream producer failed: {}: {}", e, ctx.request.path));
+                ctx.set_response(HttpResponse::new(500));
+            }
+            Err(_) => ctx.set_response(HttpResponse::new(500)),
+        }
+    })
+}
+
+// 超过上限, 保留状态码与响应头, 以剩余的数据重新组成流式响应
+fn pass_through(
+    response: &HttpResponse,
+    buffered: Vec<u8>,
+    receiver: Receiver<Vec<u8>>,
+    producing: thread::JoinHandle<io::Result<()>>,
+) -> HttpResponse {
+    let content_type = response.header("Content-Type").unwrap_or_default().to_string();
+    let mut streaming = HttpResponse::stream(content_type, move |out| {
+        out.write_all(&buffered)?;
+        for chunk in receiver.iter() {
+            out.write_all(&chunk)?;
+        }
+        producing
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("stream producer panicked")))
+    });
+    streaming.status_code = response.status_code;
+    streaming.headers = response.headers.clone();
+    streaming
+}
+
+// 接收方(发送连接出错或已结束)不再读取时写入失败, 生成函数随之结束
+struct ChannelWriter(SyncSender<Vec<u8>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .send(buf.to_vec())
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+// 限制同一路由同时执行的请求数
+#[derive(Debug)]
+pub struct Bulkhead {
+    limit: usize,
+    wait: Duration,
+    active: Mutex<usize>,
+    released: Condvar,
+}
+
+pub struct BulkheadPermit<'a> {
+    bulkhead: &'a Bulkhead,
+}
+
+impl Bulkhead {
+    pub fn new(limit: usize, wait: Duration) -> Bulkhead {
+        Bulkhead {
+            limit,
+            wait,
+            active: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    // 最多等待 wait, 仍然没有空位时返回 None
+    pub fn acquire(&self) -> Option<BulkheadPermit<'_>> {
+        let deadline = Instant::now() + self.wait;
+        let mut active = self.active.lock().unwrap();
+        while *active >= self.limit {
+            let remaining = deadline.checked_duration_since(Instant::now())?;
+            let (guard, result) = self.released.wait_timeout(active, remaining).unwrap();
+            active = guard;
+            if result.timed_out() && *active >= self.limit {
+                return None;
+            }
+        }
+        *active += 1;
+        Some(BulkheadPermit { bulkhead: self })
+    }
+}
+
+impl Drop for BulkheadPermit<'_> {
+    fn drop(&mut self) {
+        *self.bulkhead.active.lock().unwrap() -= 1;
+        self.bulkhead.released.notify_one();
+    }
+}
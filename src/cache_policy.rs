@@ -0,0 +1,59 @@
+use crate::{Context, Middleware, MiddlewareChain};
+
+// 预设的缓存策略, handler 自己设置了 Cache-Control 时不覆盖
+// 例如 server.add_middleware(cache_policy::no_store_for("/api/**".into()))
+
+// 完全不缓存: 接口数据, 含敏感信息的页面
+pub fn no_store_for(path: String) -> Middleware {
+    Middleware::new(no_store).path(path)
+}
+
+// 可以缓存但每次都要重新验证(配合 ETag/304)
+pub fn no_cache_for(path: String) -> Middleware {
+    Middleware::new(no_cache).path(path)
+}
+
+// 带内容哈希的文件名, 内容永不变化
+pub fn immutable_for(path: String) -> Middleware {
+    Middleware::new(immutable).path(path)
+}
+
+fn no_store(chain: &mut MiddlewareChain, ctx: &mut Context) {
+    chain.next(ctx);
+    apply(
+        ctx,
+        false,
+        &[
+            ("Cache-Control", "no-store, no-cache, must-revalidate, max-age=0"),
+            ("Pragma", "no-cache"),
+            ("Expires", "0"),
+        ],
+    );
+}
+
+fn no_cache(chain: &mut MiddlewareChain, ctx: &mut Context) {
+    chain.next(ctx);
+    apply(ctx, false, &[("Cache-Control", "no-cache"), ("Pragma", "no-cache")]);
+}
+
+fn immutable(chain: &mut MiddlewareChain, ctx: &mut Context) {
+    chain.next(ctx);
+    apply(ctx, true, &[("Cache-Control", "public, max-age=31536000, immutable")]);
+}
+
+fn apply(ctx: &mut Context, success_only: bool, headers: &[(&str, &str)]) {
+    let Some(response) = ctx.response.as_mut() else {
+        return;
+    };
+    // 错误响应不能被长期缓存
+    if success_only && !(200..400).contains(&response.status_code) {
+        return;
+    }
+    if response.header("Cache-Control").is_some() {
+        return;
+    }
+    let response_headers = response.headers.get_or_insert_with(Default::default);
+    for (key, value) in headers {
+        response_headers.insert(key.to_string(), value.to_string());
+    }
+}
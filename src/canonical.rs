@@ -0,0 +1,97 @@
+use crate::path_pattern::is_path_match;
+use crate::{host_name, Context, HttpMethod, HttpResponse, Middleware};
+
+// 把请求重定向到规范的主机名与协议, 如 www.example.com -> example.com, http -> https,
+// 避免同一内容出现在多个地址上; 健康检查、ACME 验证等路径可以排除
+
+/// 规范地址的配置, 用 middleware() 转为中间件
+///
+/// 如 server.add_middleware(CanonicalHost::new().host("example.com").scheme("https").except("/.well-known/acme-challenge/**").middleware())
+#[derive(Debug, Clone, Default)]
+pub struct CanonicalHost {
+    host: Option<String>,
+    scheme: Option<String>,
+    exceptions: Vec<String>,
+    trust_forwarded_proto: bool,
+}
+
+impl CanonicalHost {
+    /// 主机名与协议都不限制, 用 host、scheme 设置
+    pub fn new() -> CanonicalHost {
+        CanonicalHost::default()
+    }
+    /// 规范主机名, 可以带端口, 如 "example.com", "localhost:8080"; 不设置时保持请求的 Host
+    pub fn host(mut self, host: &str) -> Self {
+        self.host = Some(host.to_ascii_lowercase());
+        self
+    }
+    /// "http" 或 "https"; 不设置时保持请求的协议
+    pub fn scheme(mut self, scheme: &str) -> Self {
+        self.scheme = Some(scheme.to_ascii_lowercase());
+        self
+    }
+    /// 不重定向的路径模式, 如 "/healthz", "/.well-known/acme-challenge/**"
+    pub fn except(mut self, path: &str) -> Self {
+        self.exceptions.push(path.to_string());
+        self
+    }
+    /// 以 X-Forwarded-Proto 作为请求的协议, 只应在 TLS 由反向代理终止时开启
+    pub fn trust_forwarded_proto(mut self) -> Self {
+        self.trust_forwarded_proto = true;
+        self
+    }
+
+    /// GET、HEAD 重定向为 301, 其他方法为 308(保持方法与请求体)
+    pub fn middleware(self) -> Middleware {
+        Middleware::new(move |chain, ctx| match self.redirect_location(ctx) {
+            Some(location) => {
+                let status_code = match ctx.request.method {
+                    HttpMethod::GET | HttpMethod::HEAD => 301,
+                    _ => 308,
+                };
+                ctx.set_response(
+                    HttpResponse::new(status_code)
+                        .add_header("Location".into(), location)
+                        .add_header("Content-Length".into(), "0".into()),
+                );
+            }
+            None => chain.next(ctx),
+        })
+    }
+
+    // 已经是规范地址、路径被排除或无法确定请求的主机名时为 None
+    fn redirect_location(&self, ctx: &Context) -> Option<String> {
+        let request = &ctx.request;
+        if self.exceptions.iter().any(|pattern| is_path_match(pattern, &request.path)) {
+            return None;
+        }
+        let host_header = request.header("Host")?.trim().to_ascii_lowercase();
+        // 格式不合法的 Host 不能写进 Location
+        let name = host_name(&host_header)?;
+        let forwarded = request
+            .header("X-Forwarded-Proto")
+            .filter(|_| self.trust_forwarded_proto)
+            .and_then(|proto| proto.split(',').next())
+            .map(|proto| proto.trim().to_ascii_lowercase());
+        let scheme = forwarded.unwrap_or_else(|| if request.is_secure() { "https" } else { "http" }.to_string());
+
+        let host_ok = self.host.as_ref().is_none_or(|host| {
+            host == &host_header || (!host.contains(':') && host == &name)
+        });
+        let scheme_ok = self.scheme.as_ref().is_none_or(|expected| expected == &scheme);
+        if host_ok && scheme_ok {
+            return None;
+        }
+        let mut location = format!(
+            "{}://{}{}",
+            self.scheme.as_deref().unwrap_or(&scheme),
+            self.host.as_deref().unwrap_or(&host_header),
+            request.path
+        );
+        if !request.query_string.is_empty() {
+            location.push('?');
+            location.push_str(&request.query_string);
+        }
+        Some(location)
+    }
+}
@@ -0,0 +1,234 @@
+use crate::media_type::MediaType;
+use crate::negotiation::preferred_encoding;
+use crate::{HttpRequest, HttpResponse};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use std::collections::HashMap;
+use std::fs::{File, Metadata};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+// 按 Accept-Encoding 压缩文本类响应体, 见 HttpServer::compress_responses
+// 内存中的响应体整体压缩并给出 Content-Length; 流式响应与较大的文件边读边压缩, 以 chunked 发送
+// 较小的文件压缩后放入 FileCache, 之后直接发送缓存的内容
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Encoding {
+    Gzip,
+    // HTTP 中的 deflate 指 zlib 格式(RFC 1950), 而不是裸 deflate 数据
+    Deflate,
+}
+
+impl Encoding {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+}
+
+// 客户端接受的编码中 q 值最大的一个, 相同时优先 gzip; 不压缩更合适时为 None
+pub(crate) fn negotiate(request: &HttpRequest) -> Option<Encoding> {
+    match preferred_encoding(request.header("Accept-Encoding")?, &["gzip", "deflate", "identity"])? {
+        "gzip" => Some(Encoding::Gzip),
+        "deflate" => Some(Encoding::Deflate),
+        _ => None,
+    }
+}
+
+// 文本类内容才值得压缩, 图片、压缩包等本身已经压缩过
+pub(crate) fn is_compressible(content_type: &str) -> bool {
+    let Some(media_type) = MediaType::parse(content_type) else {
+        return false;
+    };
+    let sub_type = media_type.sub_type.to_ascii_lowercase();
+    match media_type.main_type.to_ascii_lowercase().as_str() {
+        "text" => true,
+        "application" => {
+            matches!(sub_type.as_str(), "json" | "javascript" | "xml" | "x-ndjson" | "wasm")
+                || sub_type.ends_with("+json")
+                || sub_type.ends_with("+xml")
+        }
+        "image" => sub_type == "svg+xml",
+        _ => false,
+    }
+}
+
+// 请求或响应的 Cache-Control 带 no-transform 时不能改变响应体的编码(RFC 9111 5.2.1.6, 5.2.2.6)
+pub(crate) fn is_no_transform(cache_control: Option<&str>) -> bool {
+    cache_control.is_some_and(|value| {
+        value
+            .split(',')
+            .any(|directive| directive.trim().eq_ignore_ascii_case("no-transform"))
+    })
+}
+
+pub(crate) fn compress(content: &[u8], encoding: Encoding) -> io::Result<Vec<u8>> {
+    let mut encoder = Encoder::new(Vec::new(), encoding);
+    encoder.write_all(content)?;
+    encoder.finish()
+}
+
+pub(crate) const DEFAULT_FILE_CACHE_BYTES: u64 = 16 * 1024 * 1024;
+
+struct CachedFile {
+    mtime: SystemTime,
+    len: u64,
+    data: Arc<Vec<u8>>,
+    // 最近一次使用时的 tick
+    used: u64,
+}
+
+#[derive(Default)]
+struct CachedFiles {
+    tick: u64,
+    // 压缩后的总字节数
+    bytes: u64,
+    map: HashMap<(PathBuf, Encoding), CachedFile>,
+}
+
+// 压缩后的文件内容, 按 (路径, 编码) 缓存, mtime 或大小变化时重新压缩, 超出总字节数时淘汰最久没用过的
+pub(crate) struct FileCache {
+    max_bytes: u64,
+    files: Mutex<CachedFiles>,
+}
+
+impl FileCache {
+    pub(crate) fn new(max_bytes: u64) -> FileCache {
+        FileCache { max_bytes, files: Mutex::new(CachedFiles::default()) }
+    }
+
+    pub(crate) fn set_max_bytes(&mut self, max_bytes: u64) {
+        self.max_bytes = max_bytes;
+    }
+
+    // 超过缓存 1/8 的文件不缓存, 由调用方边读边压缩
+    pub(crate) fn is_cacheable(&self, len: u64) -> bool {
+        len <= self.max_bytes / 8
+    }
+
+    pub(crate) fn get_or_compress(&self, path: &Path, metadata: &Metadata, file: &mut File, encoding: Encoding) -> io::Result<Arc<Vec<u8>>> {
+        let mtime = metadata.modified()?;
+        let key = (path.to_path_buf(), encoding);
+        {
+            let files = &mut *self.files.lock().unwrap();
+            if let Some(cached) = files.map.get_mut(&key)
+                && cached.mtime == mtime
+                && cached.len == metadata.len()
+            {
+                files.tick += 1;
+                cached.used = files.tick;
+                return Ok(Arc::clone(&cached.data));
+            }
+        }
+        // 压缩期间不持锁
+        let mut encoder = Encoder::new(Vec::new(), encoding);
+        io::copy(file, &mut encoder)?;
+        let data = Arc::new(encoder.finish()?);
+        let files = &mut *self.files.lock().unwrap();
+        if let Some(stale) = files.map.remove(&key) {
+            files.bytes -= stale.data.len() as u64;
+        }
+        files.bytes += data.len() as u64;
+        files.tick += 1;
+        let cached = CachedFile { mtime, len: metadata.len(), data: Arc::clone(&data), used: files.tick };
+        files.map.insert(key, cached);
+        while files.bytes > self.max_bytes {
+            let Some(oldest) = files.map.iter().min_by_key(|(_, cached)| cached.used).map(|(key, _)| key.clone()) else {
+                break;
+            };
+            if let Some(evicted) = files.map.remove(&oldest) {
+                files.bytes -= evicted.data.len() as u64;
+            }
+        }
+        Ok(data)
+    }
+}
+
+// 压缩后的内容与原内容字节不同, 强 ETag 改为弱 ETag
+pub(crate) fn mark_encoded(response: &mut HttpResponse, encoding: Encoding) {
+    response.set_header("Content-Encoding", encoding.name().into());
+    response.remove_header("Content-Length");
+    if let Some(etag) = response.header("ETag").filter(|etag| !etag.starts_with("W/")) {
+        let weak = format!("W/{}", etag);
+        response.set_header("ETag", weak);
+    }
+}
+
+pub(crate) enum Encoder<W: Write> {
+    Gzip(GzEncoder<W>),
+    Deflate(ZlibEncoder<W>),
+}
+
+impl<W: Write> Encoder<W> {
+    pub(crate) fn new(out: W, encoding: Encoding) -> Encoder<W> {
+        match encoding {
+            Encoding::Gzip => Encoder::Gzip(GzEncoder::new(out, Compression::default())),
+            Encoding::Deflate => Encoder::Deflate(ZlibEncoder::new(out, Compression::default())),
+        }
+    }
+    // 写出剩余数据与结尾(gzip 的 CRC 与长度), 不调用时内容不完整
+    pub(crate) fn finish(self) -> io::Result<W> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Deflate(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            Encoder::Deflate(encoder) => encoder.write(buf),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Deflate(encoder) => encoder.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    fn cached(cache: &FileCache, path: &Path) -> Arc<Vec<u8>> {
+        let mut file = File::open(path).unwrap();
+        let metadata = file.metadata().unwrap();
+        cache.get_or_compress(path, &metadata, &mut file, Encoding::Gzip).unwrap()
+    }
+
+    fn gunzip(data: &[u8]) -> String {
+        let mut text = String::new();
+        GzDecoder::new(data).read_to_string(&mut text).unwrap();
+        text
+    }
+
+    #[test]
+    fn compressed_files_are_cached_until_they_change() {
+        let dir = std::env::temp_dir().join(format!("compressed-cache-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.js");
+        std::fs::write(&path, "console.log(1);").unwrap();
+        let cache = FileCache::new(DEFAULT_FILE_CACHE_BYTES);
+
+        let first = cached(&cache, &path);
+        assert_eq!(gunzip(&first), "console.log(1);");
+        // 命中时返回同一份压缩结果
+        assert!(Arc::ptr_eq(&first, &cached(&cache, &path)));
+
+        std::fs::write(&path, "console.log(12);").unwrap();
+        let changed = cached(&cache, &path);
+        assert!(!Arc::ptr_eq(&first, &changed));
+        assert_eq!(gunzip(&changed), "console.log(12);");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,32 @@
+use crate::url::percent_decode;
+
+// Cookie 请求头(RFC 6265 5.4): name=value; name2=value2
+// 浏览器按路径更长、创建更早的顺序发送同名 cookie, 因此保留顺序与重复的名称
+
+/// 解析 Cookie 请求头, 值去掉两端的双引号后做百分号解码(+ 保持原样); 没有 = 或名称为空的项忽略
+///
+/// ```
+/// use rustbook_httpserver::cookie;
+///
+/// let cookies = cookie::parse(r#"sid=a%20b; theme="dark"; flag; =x; sid=old"#);
+/// let cookies = cookies.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect::<Vec<_>>();
+/// assert_eq!(cookies, [("sid", "a b"), ("theme", "dark"), ("sid", "old")]);
+/// ```
+pub fn parse(header: &str) -> Vec<(String, String)> {
+    header
+        .split(';')
+        .filter_map(|pair| {
+            let (name, value) = pair.split_once('=')?;
+            let name = name.trim();
+            if name.is_empty() {
+                return None;
+            }
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value);
+            Some((name.to_string(), percent_decode(value, false)))
+        })
+        .collect()
+}
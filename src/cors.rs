@@ -0,0 +1,156 @@
+use crate::{HttpMethod, HttpRequest, HttpResponse};
+use std::time::Duration;
+
+// 跨域资源共享
+// 预检请求(带 Origin 与 Access-Control-Request-Method 的 OPTIONS)按目标路由的策略直接回复, 不经过中间件与 handler;
+// 实际请求在响应上补充 Access-Control-Allow-Origin 等头, 不允许的来源不加任何 CORS 头, 由浏览器拦截
+
+/// 跨域策略, HttpServer::cors 设置全局策略, RequestMapping::cors 为单个路由覆盖
+///
+/// 如 CorsPolicy::new().allow_origin("https://example.com").allow_header("Content-Type").max_age(Duration::from_secs(600))
+#[derive(Debug, Clone, Default)]
+pub struct CorsPolicy {
+    origins: Vec<String>,
+    methods: Vec<HttpMethod>,
+    headers: Vec<String>,
+    expose_headers: Vec<String>,
+    credentials: bool,
+    max_age: Option<Duration>,
+}
+
+impl CorsPolicy {
+    /// 不允许任何来源, 用 allow_origin 添加
+    pub fn new() -> CorsPolicy {
+        CorsPolicy::default()
+    }
+    /// 如 "https://example.com"; "*" 允许任意来源, 与 allow_credentials 一起使用时回显请求的 Origin
+    pub fn allow_origin(mut self, origin: &str) -> Self {
+        self.origins.push(origin.to_string());
+        self
+    }
+    /// 不设置时允许路由本身的方法
+    pub fn allow_method(mut self, method: HttpMethod) -> Self {
+        self.methods.push(method);
+        self
+    }
+    /// 预检请求的 Access-Control-Request-Headers 中允许出现的请求头, "*" 允许任意请求头
+    pub fn allow_header(mut self, name: &str) -> Self {
+        self.headers.push(name.to_string());
+        self
+    }
+    /// 允许浏览器中的脚本读取的响应头
+    pub fn expose_header(mut self, name: &str) -> Self {
+        self.expose_headers.push(name.to_string());
+        self
+    }
+    /// 允许携带 Cookie 等凭据
+    pub fn allow_credentials(mut self) -> Self {
+        self.credentials = true;
+        self
+    }
+    /// 浏览器缓存预检结果的时间, 期间同样的请求不再预检; 不设置时由浏览器决定(通常只有几秒)
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    // Access-Control-Allow-Origin 的值, 不允许时为 None
+    fn allowed_origin(&self, origin: &str) -> Option<String> {
+        if self.origins.iter().any(|o| o == "*") {
+            return Some(if self.credentials { origin } else { "*" }.to_string());
+        }
+        self.origins
+            .iter()
+            .any(|o| o.eq_ignore_ascii_case(origin))
+            .then(|| origin.to_string())
+    }
+
+    fn add_origin_headers(&self, allowed_origin: String, response: &mut HttpResponse) {
+        if allowed_origin != "*" {
+            response.merge_vary("Origin");
+        }
+        response.set_header("Access-Control-Allow-Origin", allowed_origin);
+        if self.credentials {
+            response.set_header("Access-Control-Allow-Credentials", "true".into());
+        }
+    }
+
+    // route_methods 为目标路由的方法, 为空表示任意方法; 不允许时返回 403
+    pub(crate) fn preflight(&self, request: &HttpRequest, route_methods: &[HttpMethod]) -> HttpResponse {
+        let forbidden = HttpResponse::new(403).add_vary("Origin");
+        let (Some(origin), Some(method)) = (
+            request.header("Origin"),
+            request.header("Access-Control-Request-Method"),
+        ) else {
+            return forbidden;
+        };
+        let Some(allowed_origin) = self.allowed_origin(origin) else {
+            return forbidden;
+        };
+        let Some(method) = HttpMethod::name_of(method.to_string()) else {
+            return forbidden;
+        };
+        let methods = if self.methods.is_empty() { route_methods } else { &self.methods };
+        if !methods.is_empty() && !methods.contains(&method) {
+            return forbidden;
+        }
+        let requested_headers = request
+            .header("Access-Control-Request-Headers")
+            .map(|headers| {
+                headers
+                    .split(',')
+                    .map(|h| h.trim())
+                    .filter(|h| !h.is_empty())
+                    .collect::<Vec<&str>>()
+            })
+            .unwrap_or_default();
+        let any_header = self.headers.iter().any(|h| h == "*");
+        if !any_header
+            && !requested_headers
+                .iter()
+                .all(|name| self.headers.iter().any(|h| h.eq_ignore_ascii_case(name)))
+        {
+            return forbidden;
+        }
+
+        let mut response = HttpResponse::new(204);
+        self.add_origin_headers(allowed_origin, &mut response);
+        // 同一路径不同的预检请求可能得到不同的结果
+        response.merge_vary("Access-Control-Request-Method");
+        response.merge_vary("Access-Control-Request-Headers");
+        let allow_methods = if methods.is_empty() {
+            format!("{:?}", method)
+        } else {
+            methods.iter().map(|m| format!("{:?}", m)).collect::<Vec<_>>().join(", ")
+        };
+        response.set_header("Access-Control-Allow-Methods", allow_methods);
+        if !requested_headers.is_empty() {
+            response.set_header("Access-Control-Allow-Headers", requested_headers.join(", "));
+        }
+        if let Some(max_age) = self.max_age {
+            response.set_header("Access-Control-Max-Age", max_age.as_secs().to_string());
+        }
+        response
+    }
+
+    // 实际请求: 来源允许时补充响应头
+    pub(crate) fn apply(&self, request: &HttpRequest, response: &mut HttpResponse) {
+        let Some(origin) = request.header("Origin") else {
+            return;
+        };
+        let Some(allowed_origin) = self.allowed_origin(origin) else {
+            response.merge_vary("Origin");
+            return;
+        };
+        self.add_origin_headers(allowed_origin, response);
+        if !self.expose_headers.is_empty() {
+            response.set_header("Access-Control-Expose-Headers", self.expose_headers.join(", "));
+        }
+    }
+}
+
+pub(crate) fn is_preflight(request: &HttpRequest) -> bool {
+    request.method == HttpMethod::OPTIONS
+        && request.header("Origin").is_some()
+        && request.header("Access-Control-Request-Method").is_some()
+}
@@ -0,0 +1,310 @@
+use crate::hash::{constant_time_eq, md5, sha256, to_hex};
+use crate::{Context, HttpRequest, HttpResponse, Middleware};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// RFC 7616 Digest 认证, 只支持 qop=auth
+// let auth = DigestAuth::new().realm("admin").user("admin", "secret");
+// server.add_middleware(auth.middleware().path("/admin/**".into()));
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DigestAlgorithm {
+    Md5,
+    Sha256,
+}
+
+impl DigestAlgorithm {
+    fn name(&self) -> &'static str {
+        match self {
+            DigestAlgorithm::Md5 => "MD5",
+            DigestAlgorithm::Sha256 => "SHA-256",
+        }
+    }
+    fn hash(&self, input: &str) -> String {
+        match self {
+            DigestAlgorithm::Md5 => to_hex(&md5(input.as_bytes())),
+            DigestAlgorithm::Sha256 => to_hex(&sha256(input.as_bytes())),
+        }
+    }
+}
+
+/// Digest 认证的配置, middleware() 之后由中间件持有, 不同路由可以各用一份
+pub struct DigestAuth {
+    realm: String,
+    // 很多嵌入式客户端只支持 MD5, 默认保持兼容
+    algorithm: DigestAlgorithm,
+    nonce_lifetime_secs: u64,
+    users: HashMap<String, String>,
+}
+
+impl Default for DigestAuth {
+    fn default() -> Self {
+        DigestAuth {
+            realm: "rustbook-httpserver".into(),
+            algorithm: DigestAlgorithm::Md5,
+            nonce_lifetime_secs: 300,
+            users: HashMap::new(),
+        }
+    }
+}
+
+impl DigestAuth {
+    /// realm 为 "rustbook-httpserver", 算法为 MD5, 没有用户
+    pub fn new() -> DigestAuth {
+        DigestAuth::default()
+    }
+    pub fn realm(mut self, realm: &str) -> Self {
+        self.realm = realm.to_string();
+        self
+    }
+    pub fn algorithm(mut self, algorithm: DigestAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+    pub fn user(mut self, username: &str, password: &str) -> Self {
+        self.users.insert(username.to_string(), password.to_string());
+        self
+    }
+
+    /// 认证失败时回复 401 与 WWW-Authenticate
+    pub fn middleware(self) -> Middleware {
+        let verifier = Verifier {
+            config: self,
+            secret: new_secret(),
+            nonce_counts: Mutex::new(HashMap::new()),
+        };
+        Middleware::new(move |chain, ctx| match verifier.verify(&ctx.request) {
+            Verification::Ok(username) => {
+                ctx.set(DigestUser(username));
+                chain.next(ctx);
+            }
+            Verification::Stale => ctx.set_response(verifier.challenge(true)),
+            Verification::Denied => ctx.set_response(verifier.challenge(false)),
+        })
+    }
+}
+
+// 只在校验通过后设置, 请求头里未经校验的 username 不会出现在这里
+struct DigestUser(String);
+
+/// 认证通过后 handler 可以用它取用户名
+pub fn username(ctx: &Context) -> Option<&str> {
+    ctx.get::<DigestUser>().map(|user| user.0.as_str())
+}
+
+enum Verification {
+    Ok(String),
+    Stale,
+    Denied,
+}
+
+struct Verifier {
+    config: DigestAuth,
+    // 每个中间件随机的密钥, 用于签发无状态的 nonce
+    secret: String,
+    // nonce -> 已使用的最大 nc, 防重放
+    nonce_counts: Mutex<HashMap<String, u32>>,
+}
+
+impl Verifier {
+    fn verify(&self, request: &HttpRequest) -> Verification {
+        let Some(params) = request.header("Authorization").and_then(parse_authorization) else {
+            return Verification::Denied;
+        };
+        let get = |name: &str| params.get(name).map(|v| v.as_str()).unwrap_or("");
+        let config = &self.config;
+        let algorithm = config.algorithm;
+        if get("realm") != config.realm
+            || (!get("algorithm").is_empty() && !get("algorithm").eq_ignore_ascii_case(algorithm.name()))
+            || get("qop") != "auth"
+            || get("uri") != request_target(request)
+        {
+            return Verification::Denied;
+        }
+        let Some(password) = config.users.get(get("username")) else {
+            return Verification::Denied;
+        };
+        let ha1 = algorithm.hash(&format!("{}:{}:{}", get("username"), config.realm, password));
+        let ha2 = algorithm.hash(&format!("{:?}:{}", request.method, get("uri")));
+        let expected = algorithm.hash(&format!(
+            "{}:{}:{}:{}:auth:{}",
+            ha1,
+            get("nonce"),
+            get("nc"),
+            get("cnonce"),
+            ha2
+        ));
+        if !constant_time_eq(expected.as_bytes(), get("response").as_bytes()) {
+            return Verification::Denied;
+        }
+        // 签名正确但 nonce 过期时提示客户端用新 nonce 重试, 不必重新输入密码
+        let Some(issued_at) = self.check_nonce(get("nonce")) else {
+            return Verification::Denied;
+        };
+        let lifetime = config.nonce_lifetime_secs;
+        if now_secs().saturating_sub(issued_at) > lifetime {
+            return Verification::Stale;
+        }
+        let Ok(nc) = u32::from_str_radix(get("nc"), 16) else {
+            return Verification::Denied;
+        };
+        let mut nonce_counts = self.nonce_counts.lock().unwrap();
+        nonce_counts.retain(|nonce, _| {
+            self.check_nonce(nonce)
+                .is_some_and(|at| now_secs().saturating_sub(at) <= lifetime)
+        });
+        let last = nonce_counts.entry(get("nonce").to_string()).or_insert(0);
+        if nc <= *last {
+            return Verification::Denied;
+        }
+        *last = nc;
+        Verification::Ok(get("username").to_string())
+    }
+
+    fn challenge(&self, stale: bool) -> HttpResponse {
+        let mut value = format!(
+            "Digest realm=\"{}\", qop=\"auth\", algorithm={}, nonce=\"{}\", opaque=\"{}\"",
+            self.config.realm,
+            self.config.algorithm.name(),
+            self.new_nonce(),
+            &to_hex(&md5(self.secret.as_bytes()))
+        );
+        if stale {
+            value.push_str(", stale=true");
+        }
+        HttpResponse::new(401).add_header("WWW-Authenticate".into(), value)
+    }
+
+    // nonce = 签发时间.签名, 验证时不需要保存状态
+    fn new_nonce(&self) -> String {
+        let issued_at = format!("{:x}", now_secs());
+        format!("{}.{}", issued_at, self.sign(&issued_at))
+    }
+
+    fn check_nonce(&self, nonce: &str) -> Option<u64> {
+        let (issued_at, signature) = nonce.split_once('.')?;
+        if !constant_time_eq(self.sign(issued_at).as_bytes(), signature.as_bytes()) {
+            return None;
+        }
+        u64::from_str_radix(issued_at, 16).ok()
+    }
+
+    fn sign(&self, value: &str) -> String {
+        to_hex(&sha256(format!("{}:{}", value, self.secret).as_bytes()))
+    }
+}
+
+fn new_secret() -> String {
+    let state = RandomState::new();
+    format!("{:016x}{:016x}", state.hash_one(now_secs()), state.hash_one(std::process::id()))
+}
+
+fn request_target(request: &HttpRequest) -> String {
+    if request.query_string.is_empty() {
+        request.path.clone()
+    } else {
+        format!("{}?{}", request.path, request.query_string)
+    }
+}
+
+// Digest username="a", realm="b, c", nc=00000001
+fn parse_authorization(value: &str) -> Option<HashMap<String, String>> {
+    let rest = value.trim().strip_prefix("Digest ")?;
+    let mut params = HashMap::new();
+    let mut chars = rest.chars().peekable();
+    loop {
+        while chars.next_if(|c| *c == ',' || c.is_whitespace()).is_some() {}
+        let name: String = std::iter::from_fn(|| chars.next_if(|c| *c != '=' && *c != ',')).collect();
+        if name.trim().is_empty() {
+            break;
+        }
+        chars.next_if_eq(&'=')?;
+        let mut value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => value.extend(chars.next()),
+                    '"' => break,
+                    _ => value.push(c),
+                }
+            }
+        } else {
+            value = std::iter::from_fn(|| chars.next_if(|c| *c != ',')).collect();
+        }
+        params.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+    }
+    Some(params)
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HttpMethod, HttpServer, Service};
+
+    fn get(path: &str, authorization: Option<&str>) -> HttpRequest {
+        let mut raw = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n", path);
+        if let Some(value) = authorization {
+            raw.push_str(&format!("Authorization: {}\r\n", value));
+        }
+        raw.push_str("\r\n");
+        HttpRequest::parse(&mut raw.as_bytes(), "127.0.0.1:50000".into()).unwrap()
+    }
+
+    fn authorization(uri: &str, nonce: &str, nc: &str, password: &str) -> String {
+        let ha1 = to_hex(&md5(format!("admin:test:{}", password).as_bytes()));
+        let ha2 = to_hex(&md5(format!("GET:{}", uri).as_bytes()));
+        let response = to_hex(&md5(format!("{}:{}:{}:c1:auth:{}", ha1, nonce, nc, ha2).as_bytes()));
+        format!(
+            "Digest username=\"admin\", realm=\"test\", uri=\"{}\", qop=auth, nonce=\"{}\", nc={}, cnonce=\"c1\", response=\"{}\"",
+            uri, nonce, nc, response
+        )
+    }
+
+    #[test]
+    fn username_is_only_set_after_verification() {
+        let mut server = HttpServer::new("127.0.0.1:0".into());
+        server.add_middleware(DigestAuth::new().realm("test").user("admin", "secret").middleware());
+        server.add_handler(HttpMethod::GET, "/private".into(), |ctx| {
+            let user = username(ctx).unwrap_or("-").to_string();
+            ctx.set_response(HttpResponse::bytes("text/plain".into(), user.into_bytes()))
+        });
+
+        let challenge = server.call(get("/private", None));
+        assert_eq!(challenge.status_code, 401);
+        let header = challenge.header("WWW-Authenticate").unwrap().to_string();
+        let nonce = parse_authorization(&header).unwrap()["nonce"].clone();
+
+        let wrong = server.call(get("/private", Some(&authorization("/private", &nonce, "00000001", "guess"))));
+        assert_eq!(wrong.status_code, 401);
+        let ok = server.call(get("/private", Some(&authorization("/private", &nonce, "00000001", "secret"))));
+        assert_eq!(ok.status_code, 200);
+        assert_eq!(ok.body.as_deref(), Some(&b"admin"[..]));
+        // 同一个 nc 不能重放
+        let replayed = server.call(get("/private", Some(&authorization("/private", &nonce, "00000001", "secret"))));
+        assert_eq!(replayed.status_code, 401);
+    }
+
+    #[test]
+    fn middlewares_do_not_share_users() {
+        let mut server = HttpServer::new("127.0.0.1:0".into());
+        server.add_middleware(DigestAuth::new().realm("test").user("admin", "secret").middleware().path("/private".into()));
+        server.add_middleware(DigestAuth::new().realm("test").middleware().path("/other".into()));
+        server.add_handler(HttpMethod::GET, "/other".into(), |ctx| ctx.set_response(HttpResponse::new(200)));
+
+        let header = server.call(get("/other", None)).header("WWW-Authenticate").unwrap().to_string();
+        let nonce = parse_authorization(&header).unwrap()["nonce"].clone();
+        // 另一个中间件里的用户在这里不存在
+        let request = get("/other", Some(&authorization("/other", &nonce, "00000001", "secret")));
+        assert_eq!(server.call(request).status_code, 401);
+    }
+}
@@ -0,0 +1,51 @@
+use crate::etag::strong_etag;
+use crate::mime_type::get_content_type;
+use std::collections::HashMap;
+
+pub struct EmbeddedAsset {
+    pub bytes: &'static [u8],
+    pub content_type: &'static str,
+    pub etag: String,
+}
+
+// 编译进二进制的静态资源, ETag 与 Content-Type 在构造时一次性算好
+pub struct EmbeddedDir {
+    assets: HashMap<&'static str, EmbeddedAsset>,
+}
+
+impl EmbeddedDir {
+    pub fn new(files: &[(&'static str, &'static [u8])]) -> EmbeddedDir {
+        let assets = files
+            .iter()
+            .map(|(path, bytes)| {
+                let asset = EmbeddedAsset {
+                    bytes,
+                    content_type: get_content_type(path),
+                    etag: strong_etag(bytes),
+                };
+                (path.trim_start_matches('/'), asset)
+            })
+            .collect();
+        EmbeddedDir { assets }
+    }
+
+    // 目录(空路径或以 / 结尾)取其下的 index.html
+    pub fn get(&self, path: &str) -> Option<&EmbeddedAsset> {
+        let path = path.trim_start_matches('/');
+        if path.is_empty() || path.ends_with('/') {
+            return self.assets.get(format!("{}index.html", path).as_str());
+        }
+        self.assets.get(path)
+    }
+}
+
+// embedded_dir!("../static", ["index.html", "css/app.css"])
+// 路径相对于调用处的源文件, 与 include_bytes! 一致
+#[macro_export]
+macro_rules! embedded_dir {
+    ($root:literal, [$($file:literal),* $(,)?]) => {
+        $crate::embedded::EmbeddedDir::new(&[
+            $(($file, include_bytes!(concat!($root, "/", $file)) as &'static [u8])),*
+        ])
+    };
+}
@@ -0,0 +1,71 @@
+use crate::HttpError;
+use std::fmt;
+use std::io;
+
+/// 本 crate 公开 API 返回的错误
+#[derive(Debug)]
+pub enum Error {
+    /// 监听地址无法绑定, 如端口已被占用
+    Bind(io::Error),
+    /// 请求格式错误, 内容为原因
+    Parse(String),
+    /// 请求行或请求头超过长度、数量限制, 回复 431
+    HeaderTooLarge,
+    /// 请求体超过 HttpServer::max_request_body, 回复 413
+    BodyTooLarge,
+    /// HttpRequest::text 不支持请求 Content-Type 中的 charset, 内容为 charset; 可以改用 body_bytes
+    UnsupportedCharset(String),
+    /// 读写连接或文件失败
+    Io(io::Error),
+    /// 处理函数返回的错误
+    Handler(HttpError),
+    /// 等待数据超时, 如 keep-alive 连接空闲超时
+    Timeout,
+    /// 配置无效, 如证书无法读取
+    Config(String),
+    /// 线程池已关闭, 任务没有执行
+    PoolClosed,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Bind(e) => write!(f, "bind failed: {}", e),
+            Error::Parse(reason) => write!(f, "bad request: {}", reason),
+            Error::HeaderTooLarge => f.write_str("request header too large"),
+            Error::BodyTooLarge => f.write_str("request body too large"),
+            Error::UnsupportedCharset(charset) => write!(f, "unsupported charset: {}", charset),
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::Handler(e) => write!(f, "handler error: {}", e),
+            Error::Timeout => f.write_str("timed out"),
+            Error::Config(reason) => write!(f, "invalid config: {}", reason),
+            Error::PoolClosed => f.write_str("thread pool closed"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Bind(e) | Error::Io(e) => Some(e),
+            Error::Handler(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// 读超时(阻塞 socket 上表现为 WouldBlock 或 TimedOut)转为 Timeout
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Error::Timeout,
+            _ => Error::Io(e),
+        }
+    }
+}
+
+impl From<HttpError> for Error {
+    fn from(e: HttpError) -> Error {
+        Error::Handler(e)
+    }
+}
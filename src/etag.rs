@@ -0,0 +1,229 @@
+use crate::{http_date, Context, HttpMethod, HttpRequest, HttpResponse, Middleware, MiddlewareChain};
+use std::collections::HashMap;
+use std::fs::{File, Metadata};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 文件与 view 响应的 ETag 策略, 按路径模式配置, 见 HttpServer::etag_policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EtagPolicy {
+    /// mtime + size, 不读取文件内容, 默认
+    Weak,
+    /// 内容哈希, 多台服务器之间一致; 按文件缓存, mtime 或大小变化时重新计算
+    Strong,
+    /// 不生成 ETag
+    Off,
+}
+
+pub(crate) const DEFAULT_ETAG_CACHE_ENTRIES: usize = 1024;
+
+struct CachedEtag {
+    mtime: SystemTime,
+    len: u64,
+    etag: String,
+    // 最近一次使用时的 EtagCache::tick
+    used: u64,
+}
+
+// 强 ETag 按文件缓存, mtime 或大小变化时重新计算, 超出容量时淘汰最久没用过的
+pub(crate) struct EtagCache {
+    capacity: usize,
+    // (递增的使用计数, 缓存)
+    entries: Mutex<(u64, HashMap<PathBuf, CachedEtag>)>,
+}
+
+// 基于 mtime + size 的弱 ETag
+pub fn weak_etag(metadata: &Metadata) -> String {
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    format!("W/\"{:x}-{:x}\"", mtime, metadata.len())
+}
+
+// 基于内容哈希的强 ETag, 同样的内容在不同进程/机器上结果一致
+pub fn strong_etag(content: &[u8]) -> String {
+    format!("\"{:016x}-{:x}\"", fnv1a64(FNV_OFFSET, content), content.len())
+}
+
+impl EtagCache {
+    pub(crate) fn new(capacity: usize) -> EtagCache {
+        EtagCache { capacity, entries: Mutex::new((0, HashMap::new())) }
+    }
+
+    pub(crate) fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+    }
+
+    // 与 strong_etag(文件内容) 相同, 分块读取文件, 结果按 mtime + size 缓存
+    pub(crate) fn strong_file_etag(&self, path: &Path, metadata: &Metadata) -> io::Result<String> {
+        let modified = metadata.modified()?;
+        {
+            let (tick, entries) = &mut *self.entries.lock().unwrap();
+            if let Some(cached) = entries.get_mut(path)
+                && cached.mtime == modified
+                && cached.len == metadata.len()
+            {
+                *tick += 1;
+                cached.used = *tick;
+                return Ok(cached.etag.clone());
+            }
+        }
+        // 计算期间不持锁, 大文件不会阻塞其他请求
+        let mut file = File::open(path)?;
+        let mut hash = FNV_OFFSET;
+        let mut len = 0u64;
+        let mut buf = [0u8; 8192];
+        loop {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hash = fnv1a64(hash, &buf[..n]);
+            len += n as u64;
+        }
+        let etag = format!("\"{:016x}-{:x}\"", hash, len);
+        if self.capacity == 0 {
+            return Ok(etag);
+        }
+        let (tick, entries) = &mut *self.entries.lock().unwrap();
+        if !entries.contains_key(path) && entries.len() >= self.capacity {
+            let oldest = entries.iter().min_by_key(|(_, cached)| cached.used).map(|(path, _)| path.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        *tick += 1;
+        let cached = CachedEtag { mtime: modified, len: metadata.len(), etag: etag.clone(), used: *tick };
+        entries.insert(path.to_path_buf(), cached);
+        Ok(etag)
+    }
+
+    #[cfg(test)]
+    fn contains(&self, path: &Path) -> bool {
+        self.entries.lock().unwrap().1.contains_key(path)
+    }
+}
+
+// If-None-Match 弱比较, 命中则可以返回 304
+pub fn is_not_modified(request: &HttpRequest, etag: &str) -> bool {
+    if request.method != HttpMethod::GET && request.method != HttpMethod::HEAD {
+        return false;
+    }
+    let Some(if_none_match) = request.header("If-None-Match") else {
+        return false;
+    };
+    let opaque = etag.trim_start_matches("W/");
+    if_none_match
+        .split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == opaque)
+}
+
+// 为 body 响应生成强 ETag, If-None-Match 命中时改为 304
+// 例如 server.add_middleware(etag::middleware().path("/api/status".into()))
+pub fn middleware() -> Middleware {
+    Middleware::new(handle)
+}
+
+fn handle(chain: &mut MiddlewareChain, ctx: &mut Context) {
+    chain.next(ctx);
+    let Some(response) = ctx.response.as_mut() else {
+        return;
+    };
+    if response.status_code != 200 || response.header("ETag").is_some() {
+        return;
+    }
+    // view/file 响应由 handler_response 按文件元数据处理
+    let Some(content) = response.body.as_deref() else {
+        return;
+    };
+    let etag = strong_etag(content);
+    response
+        .headers
+        .get_or_insert_with(Default::default)
+        .insert("ETag".into(), etag);
+    if let Some(not_modified) = not_modified_response(&ctx.request, response) {
+        ctx.set_response(not_modified);
+    }
+}
+
+// 200 响应在 If-None-Match(或没有它时的 If-Modified-Since)命中时对应的 304, 保留 ETag 与 Cache-Control
+pub(crate) fn not_modified_response(request: &HttpRequest, response: &HttpResponse) -> Option<HttpResponse> {
+    if response.status_code != 200 {
+        return None;
+    }
+    // 两者都有时以 If-None-Match 为准, 见 RFC 9110 13.1.3
+    let not_modified = match request.header("If-None-Match") {
+        Some(_) => response.header("ETag").is_some_and(|etag| is_not_modified(request, etag)),
+        None => is_unmodified_since(request, response),
+    };
+    if !not_modified {
+        return None;
+    }
+    // RFC 9110 15.4.5: 200 响应会带的这些头 304 也要带上, 否则缓存会用错误的 Vary 保存或更新条目
+    let mut not_modified = HttpResponse::new(304);
+    for name in ["ETag", "Cache-Control", "Vary", "Content-Location", "Date", "Expires"] {
+        if let Some(value) = response.header(name) {
+            not_modified = not_modified.add_header(name.into(), value.into());
+        }
+    }
+    Some(not_modified)
+}
+
+// Last-Modified 不晚于 If-Modified-Since, 两者都精确到秒
+fn is_unmodified_since(request: &HttpRequest, response: &HttpResponse) -> bool {
+    if request.method != HttpMethod::GET && request.method != HttpMethod::HEAD {
+        return false;
+    }
+    let since = request.header("If-Modified-Since").and_then(http_date::parse);
+    let modified = response.header("Last-Modified").and_then(http_date::parse);
+    since.zip(modified).is_some_and(|(since, modified)| modified <= since)
+}
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+
+// hash 为 FNV_OFFSET 或上一段的结果, 可以分段计算
+fn fnv1a64(mut hash: u64, content: &[u8]) -> u64 {
+    for byte in content {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strong_etags_are_cached_with_lru_eviction() {
+        let dir = std::env::temp_dir().join(format!("etag-cache-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let paths = ["a", "b", "c"].map(|name| dir.join(name));
+        for path in paths.iter() {
+            std::fs::write(path, path.to_string_lossy().as_bytes()).unwrap();
+        }
+        let cache = EtagCache::new(2);
+        let etag = |path: &Path| cache.strong_file_etag(path, &std::fs::metadata(path).unwrap()).unwrap();
+
+        let a = etag(&paths[0]);
+        assert_eq!(a, strong_etag(paths[0].to_string_lossy().as_bytes()));
+        etag(&paths[1]);
+        // 再用一次 a, 之后淘汰的是 b
+        assert_eq!(etag(&paths[0]), a);
+        etag(&paths[2]);
+        assert!(cache.contains(&paths[0]));
+        assert!(!cache.contains(&paths[1]));
+        assert!(cache.contains(&paths[2]));
+
+        // 大小变化后重新计算
+        std::fs::write(&paths[0], "changed content").unwrap();
+        assert_eq!(etag(&paths[0]), strong_etag(b"changed content"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,66 @@
+use crate::{civil_time, unix_seconds};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// HTTP-date(RFC 9110 5.6.7), 用于 Last-Modified、If-Modified-Since 等请求头
+// 发送时只用 IMF-fixdate, 接收时还要兼容过时的 RFC 850 与 asctime 格式
+
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// IMF-fixdate, 如 "Sun, 06 Nov 1994 08:49:37 GMT"; 精度为秒
+///
+/// ```
+/// use rustbook_httpserver::http_date;
+/// use std::time::{Duration, UNIX_EPOCH};
+///
+/// let time = UNIX_EPOCH + Duration::from_secs(784111777);
+/// assert_eq!(http_date::format(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+/// assert_eq!(http_date::parse("Sunday, 06-Nov-94 08:49:37 GMT"), Some(time));
+/// assert_eq!(http_date::parse("Sun Nov  6 08:49:37 1994"), Some(time));
+/// ```
+pub fn format(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (year, month, day, hour, minute, second) = civil_time(seconds);
+    // 1970-01-01 为星期四
+    let weekday = WEEKDAYS[(seconds / 86400 % 7) as usize];
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        weekday,
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        hour,
+        minute,
+        second
+    )
+}
+
+/// 解析三种 HTTP-date 格式, 格式不合法时为 None; 不校验星期
+pub fn parse(value: &str) -> Option<SystemTime> {
+    let value = value.trim();
+    let (_, rest) = value.split_once([',', ' '])?;
+    let parts = rest.split_whitespace().collect::<Vec<&str>>();
+    let (year, month, day, time) = match parts.as_slice() {
+        // Sun, 06 Nov 1994 08:49:37 GMT
+        [day, month, year, time, "GMT"] => (year.parse::<i32>().ok()?, *month, day.parse::<u64>().ok()?, *time),
+        // Sunday, 06-Nov-94 08:49:37 GMT, 两位年份按 RFC 9110 取最近的过去年份, 这里简化为 1970-2069
+        [date, time, "GMT"] => {
+            let mut fields = date.split('-');
+            let (day, month, year) = (fields.next()?, fields.next()?, fields.next()?);
+            let year = year.parse::<i32>().ok()?;
+            let year = if year < 70 { 2000 + year } else { 1900 + year };
+            (year, month, day.parse::<u64>().ok()?, *time)
+        }
+        // Sun Nov  6 08:49:37 1994
+        [month, day, time, year] => (year.parse::<i32>().ok()?, *month, day.parse::<u64>().ok()?, *time),
+        _ => return None,
+    };
+    let month = MONTHS.iter().position(|m| *m == month)? as u64 + 1;
+    let mut clock = time.split(':').map(|field| field.parse::<u64>().ok());
+    let (hour, minute, second) = (clock.next()??, clock.next()??, clock.next()??);
+    if clock.next().is_some() {
+        return None;
+    }
+    let seconds = unix_seconds(year, month, day, hour, minute, second)?;
+    Some(UNIX_EPOCH + Duration::from_secs(seconds))
+}
@@ -0,0 +1,47 @@
+use std::fmt;
+use std::io;
+
+/// 处理函数返回的错误, 由服务器转换成错误响应, 见 HttpServer::add_try_handler、error_renderer 与 set_error_handler
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpError {
+    pub status_code: u16,
+    pub message: String,
+}
+
+impl HttpError {
+    pub fn new(status_code: u16, message: impl Into<String>) -> HttpError {
+        HttpError {
+            status_code,
+            message: message.into(),
+        }
+    }
+    pub fn bad_request(message: impl Into<String>) -> HttpError {
+        HttpError::new(400, message)
+    }
+    pub fn not_found(message: impl Into<String>) -> HttpError {
+        HttpError::new(404, message)
+    }
+    pub fn internal(message: impl Into<String>) -> HttpError {
+        HttpError::new(500, message)
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.status_code, self.message)
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+/// 处理函数中读文件等操作可以直接用 ?, 文件不存在为 404, 没有权限为 403, 其余为 500
+impl From<io::Error> for HttpError {
+    fn from(e: io::Error) -> HttpError {
+        let status_code = match e.kind() {
+            io::ErrorKind::NotFound => 404,
+            io::ErrorKind::PermissionDenied => 403,
+            _ => 500,
+        };
+        HttpError::new(status_code, e.to_string())
+    }
+}
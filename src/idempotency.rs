@@ -0,0 +1,284 @@
+use crate::{Context, HttpResponse, Middleware, MiddlewareChain};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+enum Entry {
+    InFlight(Instant),
+    // (完成时间, 完成的顺序, 响应)
+    Done(Instant, u64, HttpResponse),
+}
+
+// key 为 (METHOD path, Idempotency-Key)
+type StoreKey = (String, String);
+type Store = Arc<Mutex<Entries>>;
+
+#[derive(Default)]
+struct Entries {
+    map: HashMap<StoreKey, Entry>,
+    // 已缓存的响应体字节数
+    bytes: usize,
+    done: u64,
+}
+
+impl Entries {
+    fn remove(&mut self, key: &StoreKey) {
+        if let Some(Entry::Done(_, _, response)) = self.map.remove(key) {
+            self.bytes -= body_len(&response);
+        }
+    }
+
+    fn remove_expired(&mut self, ttl: Duration) {
+        let expired = self
+            .map
+            .iter()
+            .filter(|(_, entry)| match entry {
+                Entry::InFlight(at) | Entry::Done(at, _, _) => at.elapsed() >= ttl,
+            })
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        for key in expired.iter() {
+            self.remove(key);
+        }
+    }
+
+    // 超出条数或字节数上限时从最早完成的响应开始淘汰, 处理中的 key 不淘汰
+    fn insert_done(&mut self, key: StoreKey, response: HttpResponse, config: &Idempotency) {
+        self.remove(&key);
+        self.bytes += body_len(&response);
+        self.done += 1;
+        self.map.insert(key, Entry::Done(Instant::now(), self.done, response));
+        while self.map.len() > config.max_entries || self.bytes > config.max_bytes {
+            let oldest = self
+                .map
+                .iter()
+                .filter_map(|(key, entry)| match entry {
+                    Entry::Done(_, order, _) => Some((key, *order)),
+                    Entry::InFlight(_) => None,
+                })
+                .min_by_key(|(_, order)| *order)
+                .map(|(key, _)| key.clone());
+            match oldest {
+                Some(oldest) => self.remove(&oldest),
+                None => break,
+            }
+        }
+    }
+}
+
+fn body_len(response: &HttpResponse) -> usize {
+    response.body.as_ref().map_or(0, |body| body.len())
+}
+
+/// 按 Idempotency-Key 请求头缓存响应, middleware() 之后每个中间件有自己的缓存
+/// 例如 server.add_middleware(Idempotency::new().middleware().path("/pay".into()))
+pub struct Idempotency {
+    ttl: Duration,
+    max_entries: usize,
+    max_bytes: usize,
+    max_body: usize,
+}
+
+impl Default for Idempotency {
+    fn default() -> Self {
+        Idempotency {
+            ttl: Duration::from_secs(24 * 60 * 60),
+            max_entries: 10_000,
+            max_bytes: 64 * 1024 * 1024,
+            max_body: 1024 * 1024,
+        }
+    }
+}
+
+impl Idempotency {
+    /// 响应保留 24 小时, 最多 10000 条、响应体共 64 MiB
+    pub fn new() -> Idempotency {
+        Idempotency::default()
+    }
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+    /// 最多缓存的响应数, 超出时淘汰最早完成的
+    pub fn max_entries(mut self, max: usize) -> Self {
+        self.max_entries = max;
+        self
+    }
+    /// 缓存的响应体总字节数上限, 超出时淘汰最早完成的
+    pub fn max_bytes(mut self, max: usize) -> Self {
+        self.max_bytes = max;
+        self
+    }
+    /// 响应体超过 max 字节(默认 1 MiB)时不缓存, 同一个 key 的重试会再次执行 handler
+    pub fn max_body(mut self, max: usize) -> Self {
+        self.max_body = max;
+        self
+    }
+
+    /// 同一个 key 已完成时重放响应, 仍在处理中时回复 409
+    pub fn middleware(self) -> Middleware {
+        let store: Store = Arc::new(Mutex::new(Entries::default()));
+        Middleware::new(move |chain, ctx| handle(&store, &self, chain, ctx))
+    }
+}
+
+fn handle(store: &Store, config: &Idempotency, chain: &mut MiddlewareChain, ctx: &mut Context) {
+    let Some(key) = ctx.request.header("Idempotency-Key").map(|k| k.to_string()) else {
+        chain.next(ctx);
+        return;
+    };
+    let store_key = (format!("{:?} {}", ctx.request.method, ctx.request.path), key);
+    {
+        let mut entries = store.lock().unwrap();
+        entries.remove_expired(config.ttl);
+        match entries.map.get(&store_key) {
+            Some(Entry::Done(_, _, response)) => {
+                ctx.set_response(
+                    response
+                        .clone()
+                        .add_header("Idempotent-Replayed".into(), "true".into()),
+                );
+                return;
+            }
+            // 同一个 key 的请求仍在处理中
+            Some(Entry::InFlight(_)) => {
+                ctx.set_response(HttpResponse::new(409));
+                return;
+            }
+            None => {
+                entries.map.insert(store_key.clone(), Entry::InFlight(Instant::now()));
+            }
+        }
+    }
+    // handler panic 时 dispatch_request 在外层捕获, 这里的后续代码不会执行, 由 guard 清除 InFlight
+    let guard = InFlightGuard {
+        store: Arc::clone(store),
+        key: Some(store_key),
+    };
+    chain.next(ctx);
+    guard.finish(ctx.response.as_ref(), config);
+}
+
+// 持有期间 key 处于 InFlight; 没有调用 finish 就被 drop(panic 展开)时删除, 客户端可以用同一个 key 重试
+struct InFlightGuard {
+    store: Store,
+    key: Option<StoreKey>,
+}
+
+impl InFlightGuard {
+    fn finish(mut self, response: Option<&HttpResponse>, config: &Idempotency) {
+        let Some(store_key) = self.key.take() else {
+            return;
+        };
+        let mut entries = self.store.lock().unwrap();
+        match response {
+            // 5xx 不缓存, 允许客户端重试; 流式响应体只能写出一次, 也不缓存; 过大的响应体不缓存
+            Some(response) if response.status_code < 500 && !response.is_stream() && body_len(response) <= config.max_body => {
+                entries.insert_done(store_key, response.clone(), config);
+            }
+            _ => {
+                entries.remove(&store_key);
+            }
+        }
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if let Some(store_key) = self.key.take() {
+            // 展开过程中不能再 panic, 锁中毒时也要清除
+            let mut entries = self.store.lock().unwrap_or_else(|e| e.into_inner());
+            entries.remove(&store_key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HttpMethod, HttpRequest, HttpServer, Service};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn post(path: &str, key: &str) -> HttpRequest {
+        let raw = format!("POST {} HTTP/1.1\r\nHost: localhost\r\nIdempotency-Key: {}\r\n\r\n", path, key);
+        HttpRequest::parse(&mut raw.as_bytes(), "127.0.0.1:50000".into()).unwrap()
+    }
+
+    #[test]
+    fn panicking_handler_does_not_leave_key_in_flight() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut server = HttpServer::new("127.0.0.1:0".into());
+        server.add_middleware(Idempotency::new().middleware());
+        let handler_calls = Arc::clone(&calls);
+        server.add_handler(HttpMethod::POST, "/idempotency/panic".into(), move |ctx| {
+            if handler_calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                panic!("first attempt fails");
+            }
+            ctx.set_response(HttpResponse::new(201));
+        });
+
+        assert_eq!(server.call(post("/idempotency/panic", "k1")).status_code, 500);
+        // 重试时处理函数再次执行, 而不是 409
+        assert_eq!(server.call(post("/idempotency/panic", "k1")).status_code, 201);
+        let replayed = server.call(post("/idempotency/panic", "k1"));
+        assert_eq!(replayed.status_code, 201);
+        assert_eq!(replayed.header("Idempotent-Replayed"), Some("true"));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn servers_do_not_share_stored_responses() {
+        let servers: Vec<HttpServer> = (0..2)
+            .map(|_| {
+                let mut server = HttpServer::new("127.0.0.1:0".into());
+                server.add_middleware(Idempotency::new().middleware());
+                server.add_handler(HttpMethod::POST, "/idempotency/shared".into(), |ctx| {
+                    ctx.set_response(HttpResponse::new(201))
+                });
+                server
+            })
+            .collect();
+
+        assert_eq!(servers[0].call(post("/idempotency/shared", "k1")).header("Idempotent-Replayed"), None);
+        assert_eq!(servers[1].call(post("/idempotency/shared", "k1")).header("Idempotent-Replayed"), None);
+        let replayed = servers[0].call(post("/idempotency/shared", "k1"));
+        assert_eq!(replayed.header("Idempotent-Replayed"), Some("true"));
+    }
+
+    #[test]
+    fn stored_responses_are_capped() {
+        let mut server = HttpServer::new("127.0.0.1:0".into());
+        server.add_middleware(Idempotency::new().max_entries(2).max_body(8).middleware());
+        server.add_handler(HttpMethod::POST, "/idempotency/capped".into(), |ctx| {
+            let size = ctx.request.query("size").and_then(|s| s.parse().ok()).unwrap_or(0);
+            ctx.set_response(HttpResponse::bytes("text/plain".into(), vec![b'x'; size]))
+        });
+        let replayed = |path: &str, key: &str| server.call(post(path, key)).header("Idempotent-Replayed").is_some();
+
+        for key in ["k1", "k2", "k3"] {
+            assert!(!replayed("/idempotency/capped", key));
+        }
+        // 只保留最新的两条, k1 最早完成, 已被淘汰
+        assert!(replayed("/idempotency/capped", "k3"));
+        assert!(replayed("/idempotency/capped", "k2"));
+        assert!(!replayed("/idempotency/capped", "k1"));
+        // 响应体超过 max_body 的不缓存
+        assert!(!replayed("/idempotency/capped?size=9", "big"));
+        assert!(!replayed("/idempotency/capped?size=9", "big"));
+    }
+
+    #[test]
+    fn stored_body_bytes_are_capped() {
+        let mut server = HttpServer::new("127.0.0.1:0".into());
+        server.add_middleware(Idempotency::new().max_bytes(10).middleware());
+        server.add_handler(HttpMethod::POST, "/idempotency/bytes".into(), |ctx| {
+            ctx.set_response(HttpResponse::bytes("text/plain".into(), vec![b'x'; 6]))
+        });
+        let replayed = |key: &str| server.call(post("/idempotency/bytes", key)).header("Idempotent-Replayed").is_some();
+
+        assert!(!replayed("k1"));
+        assert!(!replayed("k2"));
+        assert!(replayed("k2"));
+        assert!(!replayed("k1"));
+    }
+}
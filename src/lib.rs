@@ -0,0 +1,3922 @@
+//! 基于 TcpListener 的简易 HTTP 服务器, 源自 Rust 程序设计语言一书的最后一章
+//!
+//! ```no_run
+//! use rustbook_httpserver::{HttpMethod, HttpResponse, HttpServer};
+//!
+//! let mut server = HttpServer::new("127.0.0.1:8080".into());
+//! server.add_handler(HttpMethod::GET, "/users/:id".into(), |ctx| {
+//!     let id = ctx.request.path_param("id").unwrap_or_default().to_string();
+//!     ctx.set_response(HttpResponse::json(format!(r#"{{"id": "{}"}}"#, id)));
+//! });
+//! server.run().unwrap();
+//! ```
+mod admin;
+pub mod api_key;
+pub mod buffering;
+mod bulkhead;
+pub mod cache_policy;
+pub mod canonical;
+#[cfg(feature = "compression")]
+mod compression;
+pub mod cookie;
+pub mod cors;
+pub mod digest_auth;
+pub mod embedded;
+pub mod error;
+pub mod etag;
+mod hash;
+pub mod http_date;
+pub mod http_error;
+pub mod idempotency;
+pub mod log;
+pub mod long_poll;
+pub mod media_type;
+pub mod mime_type;
+pub mod negotiation;
+pub mod path_pattern;
+mod range;
+mod reload;
+mod router;
+pub mod service;
+pub mod shutdown;
+pub mod signature;
+mod spool;
+mod ssi;
+pub mod static_dir;
+pub mod status;
+pub mod thread_pool;
+mod transport;
+pub mod url;
+pub mod vhost;
+
+use bulkhead::Bulkhead;
+use etag::{not_modified_response, weak_etag, EtagCache};
+use mime_type::get_content_type;
+use path_pattern::{capture_params, compare_specificity, is_path_match, normalize};
+use range::RangeRequest;
+use router::Router;
+use shutdown::ShutdownState;
+use spool::{Body, BodyBuffer, SpoolConfig, SpooledFile};
+use transport::Transport;
+
+pub use canonical::CanonicalHost;
+pub use cors::CorsPolicy;
+pub use embedded::EmbeddedDir;
+pub use error::Error;
+pub use etag::EtagPolicy;
+pub use http_error::HttpError;
+pub use log::LogFormat;
+pub use media_type::MediaType;
+pub use service::Service;
+pub use shutdown::ShutdownHandle;
+pub use static_dir::StaticDir;
+pub use status::StatusCode;
+pub use thread_pool::{PendingJobs, ThreadPool};
+pub use vhost::VirtualHost;
+
+use std::{
+    any::{Any, TypeId},
+    borrow::Cow,
+    cell::OnceCell,
+    cmp::Ordering,
+    collections::HashMap,
+    fmt, mem,
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{IpAddr, Shutdown, TcpListener, TcpStream},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{self, AtomicBool, AtomicU64, AtomicUsize},
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex, Weak,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// 路由处理函数, 通过 ctx.set_response 设置响应; 可以是捕获了状态(连接池、配置、计数器)的闭包
+///
+/// 用 Arc 而不是 Box, 设置了 timeout 的路由要把 handler 交给单独的线程执行
+pub type HttpHandler = Arc<dyn Fn(&mut Context) + Send + Sync>;
+/// 中间件函数, 调用 chain.next(ctx) 继续执行后续中间件与 handler
+pub type MiddlewareFunc = Box<dyn Fn(&mut MiddlewareChain, &mut Context) + Send + Sync>;
+/// 响应写出后的回调
+pub type AfterResponseHook = Box<dyn Fn(&Context) + Send + Sync>;
+/// 把处理函数返回的 HttpError 转换成响应
+pub type ErrorRenderer = Box<dyn Fn(&Context, &HttpError) -> HttpResponse + Send + Sync>;
+
+/// 一条路由, 由 HttpServer::add_handler 等返回, 用于继续配置
+pub struct RequestMapping {
+    // 为空表示任意方法
+    methods: Vec<HttpMethod>,
+    path: String,
+    handler: HttpHandler,
+    timeout: Option<Duration>,
+    timeout_threads: TimeoutThreads,
+    consumes: Vec<MediaType>,
+    produces: Vec<String>,
+    bulkhead: Option<Bulkhead>,
+    cors: Option<CorsPolicy>,
+}
+impl fmt::Debug for RequestMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequestMapping")
+            .field("methods", &self.methods)
+            .field("path", &self.path)
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+impl RequestMapping {
+    /// 超时后返回 503, 但处理线程无法被强制结束, 会在后台继续跑完
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = Some(timeout);
+        self
+    }
+    /// 设置了 timeout 时同时存在的处理线程数上限(包括超时后仍在后台运行的), 默认 64, 超出时直接返回 503
+    pub fn timeout_threads(&mut self, limit: usize) -> &mut Self {
+        self.timeout_threads.limit = limit;
+        self
+    }
+    /// 同时最多 limit 个请求在执行, 超出的最多等待 wait, 之后返回 503
+    pub fn max_concurrency(&mut self, limit: usize, wait: Duration) -> &mut Self {
+        self.bulkhead = Some(Bulkhead::new(limit, wait));
+        self
+    }
+    /// 请求体的 Content-Type 不在其中时返回 415, 支持 text/* 这样的通配
+    pub fn consumes(&mut self, media_types: &[&str]) -> &mut Self {
+        self.consumes = media_types.iter().filter_map(|m| MediaType::parse(m)).collect();
+        self
+    }
+    /// Accept 不接受其中任意一个时返回 406
+    pub fn produces(&mut self, media_types: &[&str]) -> &mut Self {
+        self.produces = media_types.iter().map(|m| m.to_string()).collect();
+        self
+    }
+    /// 覆盖 HttpServer::cors 的全局策略, 预检请求也按这里的策略回复
+    pub fn cors(&mut self, policy: CorsPolicy) -> &mut Self {
+        self.cors = Some(policy);
+        self
+    }
+    fn check_content_types(&self, request: &HttpRequest) -> Option<u16> {
+        if !self.consumes.is_empty() {
+            let accepted = match request.content_type() {
+                Some(content_type) => self.consumes.iter().any(|m| m.matches(&content_type)),
+                // 没有请求体时不做限制
+                None => !request.has_body(),
+            };
+            if !accepted {
+                return Some(415);
+            }
+        }
+        if !self.produces.is_empty()
+            && let Some(accept) = request.header("Accept")
+        {
+            let produces = self.produces.iter().map(|p| p.as_str()).collect::<Vec<&str>>();
+            if negotiation::preferred_media_type(accept, &produces).is_none() {
+                return Some(406);
+            }
+        }
+        None
+    }
+}
+
+/// 一次请求的处理上下文, 在中间件与 handler 之间传递
+pub struct Context {
+    pub request: HttpRequest,
+    pub response: Option<HttpResponse>,
+    forward_to: Option<String>,
+    route: Option<String>,
+    // 中间件与 handler 之间传递的数据, 每种类型一个值
+    // 用 Arc 保存, 设置了 timeout 的 handler 拿到的是一份共享的副本, 超时或 panic 后原值仍在
+    extensions: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+    bytes_read: u64,
+    bytes_written: u64,
+    queue_wait: Duration,
+    error: Option<HttpError>,
+}
+impl Context {
+    fn new(request: HttpRequest) -> Context {
+        Context {
+            request,
+            response: None,
+            forward_to: None,
+            route: None,
+            extensions: HashMap::new(),
+            bytes_read: 0,
+            bytes_written: 0,
+            queue_wait: Duration::ZERO,
+            error: None,
+        }
+    }
+    pub fn set_response(&mut self, response: HttpResponse) {
+        self.response = Some(response);
+    }
+    /// 处理完成后用同一个请求重新走一遍路由, 可带 query, 如 ctx.forward("/error?code=1".into())
+    pub fn forward(&mut self, path: String) {
+        self.forward_to = Some(path);
+    }
+    /// 保存一个按类型区分的值供后续中间件与 handler 读取, 如认证后的用户; 返回同类型的旧值
+    pub fn set<T: Any + Send + Sync>(&mut self, value: T) -> Option<T> {
+        self.extensions
+            .insert(TypeId::of::<T>(), Arc::new(value))
+            .and_then(|old| old.downcast().ok())
+            .and_then(|old| Arc::try_unwrap(old).ok())
+    }
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.extensions.get(&TypeId::of::<T>())?.downcast_ref()
+    }
+    /// 设置了 timeout 的 handler 中, 中间件放入的值与外层共享, 此时为 None, 可以用 set 替换
+    pub fn get_mut<T: Any + Send + Sync>(&mut self) -> Option<&mut T> {
+        Arc::get_mut(self.extensions.get_mut(&TypeId::of::<T>())?)?.downcast_mut()
+    }
+    /// 与 get_mut 相同, 值被共享时只移除不返回
+    pub fn remove<T: Any + Send + Sync>(&mut self) -> Option<T> {
+        self.extensions
+            .remove(&TypeId::of::<T>())
+            .and_then(|value| value.downcast().ok())
+            .and_then(|value| Arc::try_unwrap(value).ok())
+    }
+    /// 匹配到的 handler 的路由模式, 如 /users/:id; 没有匹配到 handler 时为 None
+    pub fn route(&self) -> Option<&str> {
+        self.route.as_deref()
+    }
+    /// 请求行、请求头与请求体(含分块编码)在连接上占用的字节数, 响应写出后才有值
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+    /// 写到连接上的字节数, 包括状态行与响应头, 响应写出后才有值
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+    /// 请求在线程池队列中等待工作线程的时间, 与处理耗时分开统计, 用于判断线程池是否过小
+    ///
+    /// 连接上的第一个请求包含连接排队的时间; 开启 handler_threads 时还包括等待 handler 线程的时间
+    pub fn queue_wait(&self) -> Duration {
+        self.queue_wait
+    }
+    /// add_try_handler 注册的处理函数返回的错误, 此时 response 为 error_renderer 生成的响应
+    pub fn error(&self) -> Option<&HttpError> {
+        self.error.as_ref()
+    }
+}
+
+const MAX_FORWARDS: usize = 8;
+
+// TRACE 回显时去掉的敏感请求头
+const TRACE_EXCLUDED_HEADERS: [&str; 5] = [
+    "Authorization",
+    "Proxy-Authorization",
+    "Cookie",
+    "X-API-Key",
+    "X-Signature",
+];
+
+/// 中间件, 默认作用于所有方法与路径 /**
+pub struct Middleware {
+    name: String,
+    method: Option<HttpMethod>,
+    path: String,
+    order: usize,
+    handler: MiddlewareFunc,
+}
+impl fmt::Debug for Middleware {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Middleware")
+            .field("name", &self.name)
+            .field("method", &self.method)
+            .field("path", &self.path)
+            .field("order", &self.order)
+            .finish_non_exhaustive()
+    }
+}
+impl Middleware {
+    /// handler 可以是捕获了状态的闭包
+    pub fn new<F>(handler: F) -> Self
+    where
+        F: Fn(&mut MiddlewareChain, &mut Context) + Send + Sync + 'static,
+    {
+        Middleware {
+            name: String::new(),
+            method: None,
+            path: "/**".to_string(),
+            order: 0,
+            handler: Box::new(handler),
+        }
+    }
+    /// 仅用于日志与 describe_middlewares
+    pub fn name(mut self, name: String) -> Self {
+        self.name = name;
+        self
+    }
+    pub fn method(mut self, method: HttpMethod) -> Self {
+        self.method = Some(method);
+        self
+    }
+    /// 路径模式, 语法与路由相同
+    pub fn path(mut self, path: String) -> Self {
+        self.path = path;
+        self
+    }
+    /// 小的在外层, 相同时按注册顺序
+    ///
+    /// ```
+    /// use rustbook_httpserver::{HttpMethod, HttpRequest, HttpResponse, HttpServer, Middleware, Service};
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let events = Arc::new(Mutex::new(Vec::new()));
+    /// let mut server = HttpServer::new("127.0.0.1:0".into());
+    /// server.add_handler(HttpMethod::GET, "/api/users".into(), |ctx| ctx.set_response(HttpResponse::new(200)));
+    ///
+    /// let auth_events = Arc::clone(&events);
+    /// server.add_middleware(
+    ///     Middleware::new(move |chain, ctx| {
+    ///         auth_events.lock().unwrap().push("auth-before".to_string());
+    ///         if ctx.request.header("Authorization").is_some() {
+    ///             chain.next(ctx);
+    ///         } else {
+    ///             chain.abort_with(ctx, HttpResponse::new(401));
+    ///         }
+    ///         auth_events.lock().unwrap().push("auth-after".to_string());
+    ///     })
+    ///     .name("auth".into())
+    ///     .order(10),
+    /// );
+    /// // 后注册, 但 order 更小, 仍然包在认证外面, 能记录到被拒绝的请求
+    /// let log_events = Arc::clone(&events);
+    /// server.add_middleware(
+    ///     Middleware::new(move |chain, ctx| {
+    ///         log_events.lock().unwrap().push("log-before".to_string());
+    ///         chain.next(ctx);
+    ///         let status = ctx.response.as_ref().map_or(0, |response| response.status_code);
+    ///         log_events.lock().unwrap().push(format!("log-after {}", status));
+    ///     })
+    ///     .name("log".into())
+    ///     .order(0),
+    /// );
+    ///
+    /// let request = |raw: &str| HttpRequest::parse(&mut raw.as_bytes(), "127.0.0.1:50000".into()).unwrap();
+    /// let ok = server.call(request("GET /api/users HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer t\r\n\r\n"));
+    /// assert_eq!(ok.status_code, 200);
+    /// assert_eq!(*events.lock().unwrap(), ["log-before", "auth-before", "auth-after", "log-after 200"]);
+    ///
+    /// // 认证拒绝请求时 handler 不执行, 外层的日志仍然看到 401
+    /// events.lock().unwrap().clear();
+    /// let rejected = server.call(request("GET /api/users HTTP/1.1\r\nHost: localhost\r\n\r\n"));
+    /// assert_eq!(rejected.status_code, 401);
+    /// assert_eq!(*events.lock().unwrap(), ["log-before", "auth-before", "auth-after", "log-after 401"]);
+    ///
+    /// assert_eq!(
+    ///     server.describe_middlewares(&HttpMethod::GET, "/api/users"),
+    ///     ["log order=0 None /**", "auth order=10 None /**"]
+    /// );
+    /// ```
+    pub fn order(mut self, order: usize) -> Self {
+        self.order = order;
+        self
+    }
+}
+
+/// 一次请求匹配到的中间件链, 末尾是 handler
+///
+/// 没有匹配到 handler(404、内置资源)或路由拒绝请求(415、406、503)时末尾是固定的响应,
+/// 因此中间件在 chain.next(ctx) 之后总能看到并修改 handler 阶段产生的响应
+pub struct MiddlewareChain<'a> {
+    end: ChainEnd<'a>,
+    middlewares: Vec<&'a Middleware>,
+    abort_index: i8,
+    index: i8,
+    timeout: Option<(Duration, &'a TimeoutThreads)>,
+    error_renderer: Option<&'a ErrorRenderer>,
+}
+
+enum ChainEnd<'a> {
+    Handler(&'a HttpHandler),
+    Response(Option<HttpResponse>),
+}
+
+impl<'a> MiddlewareChain<'a> {
+    fn new(handler: &'a HttpHandler, middlewares: Vec<&'a Middleware>) -> Self {
+        MiddlewareChain::with_end(ChainEnd::Handler(handler), middlewares)
+    }
+    fn respond(response: HttpResponse, middlewares: Vec<&'a Middleware>) -> Self {
+        MiddlewareChain::with_end(ChainEnd::Response(Some(response)), middlewares)
+    }
+    fn with_end(end: ChainEnd<'a>, middlewares: Vec<&'a Middleware>) -> Self {
+        MiddlewareChain {
+            end,
+            middlewares,
+            abort_index: -1,
+            index: 0,
+            timeout: None,
+            error_renderer: None,
+        }
+    }
+    pub fn is_abort(&self) -> bool {
+        self.abort_index != -1
+    }
+    /// 之后的 next 不再执行后续中间件与 handler; 此时还没有响应的话连接直接关闭, 需要响应时用 abort_with
+    pub fn abort(&mut self) {
+        self.abort_index = self.index;
+    }
+    /// 以 response 结束请求, 如认证失败时 chain.abort_with(ctx, HttpResponse::new(401).body("login required"))
+    pub fn abort_with(&mut self, ctx: &mut Context, response: HttpResponse) {
+        ctx.set_response(response);
+        self.abort();
+    }
+    pub fn next(&mut self, ctx: &mut Context) {
+        if self.is_abort() {
+            return;
+        }
+        if self.index < self.middlewares.len() as i8 {
+            let i = self.index as usize;
+            let middleware = self.middlewares.get(i);
+            if let Some(md) = middleware {
+                self.index += 1;
+                (md.handler)(self, ctx);
+                return;
+            }
+        }
+        match &mut self.end {
+            ChainEnd::Handler(handler) => {
+                match self.timeout {
+                    Some((timeout, threads)) => call_with_timeout(Arc::clone(handler), ctx, timeout, threads),
+                    None => handler(ctx),
+                }
+                // 在这里转换, 外层中间件在 chain.next(ctx) 之后看到的是最终的错误响应
+                if let Some(error) = ctx.error.as_ref() {
+                    if error.status_code >= 500 {
+                        log::error(&format!("[{}] {} failed: {}", ctx.request.id(), ctx.request.path, error));
+                    }
+                    let response = match self.error_renderer {
+                        Some(render) => render(ctx, error),
+                        // 由 set_error_handler 的错误页或默认规则补充响应体
+                        None => HttpResponse::new(error.status_code),
+                    };
+                    ctx.set_response(response);
+                }
+            }
+            ChainEnd::Response(response) => {
+                if let Some(response) = response.take() {
+                    ctx.set_response(response);
+                }
+            }
+        }
+    }
+}
+
+// 设置了 timeout 的路由正在运行的处理线程, 超时的线程跑完之前一直占着名额
+#[derive(Debug)]
+struct TimeoutThreads {
+    limit: usize,
+    running: Arc<AtomicUsize>,
+}
+
+fn call_with_timeout(handler: HttpHandler, ctx: &mut Context, timeout: Duration, threads: &TimeoutThreads) {
+    if threads.running.fetch_add(1, atomic::Ordering::SeqCst) >= threads.limit {
+        threads.running.fetch_sub(1, atomic::Ordering::SeqCst);
+        log::warn(&format!("too many timed handler threads: {}", ctx.request.path));
+        ctx.set_response(HttpResponse::new(503));
+        return;
+    }
+    let running = Arc::clone(&threads.running);
+    let (sender, receiver) = mpsc::channel();
+    let mut owned = Context::new(ctx.request.clone());
+    owned.response = ctx.response.take();
+    owned.forward_to = ctx.forward_to.take();
+    owned.route = ctx.route.clone();
+    owned.queue_wait = ctx.queue_wait;
+    owned.error = ctx.error.take();
+    // 只复制 Arc, 超时或 panic 时 ctx 上的值原样保留
+    owned.extensions = ctx.extensions.clone();
+    thread::spawn(move || {
+        let completed = panic::catch_unwind(AssertUnwindSafe(|| handler(&mut owned))).is_ok();
+        running.fetch_sub(1, atomic::Ordering::SeqCst);
+        let _ = sender.send(completed.then_some(owned));
+    });
+    match receiver.recv_timeout(timeout) {
+        Ok(Some(done)) => *ctx = done,
+        Err(RecvTimeoutError::Timeout) => {
+            log::warn(&format!("handler timeout after {:?}: {}", timeout, ctx.request.path));
+            ctx.set_response(HttpResponse::new(503));
+        }
+        // handler panic
+        Ok(None) | Err(RecvTimeoutError::Disconnected) => ctx.set_response(HttpResponse::new(500)),
+    }
+}
+
+// 错误页与 static_fallback 的 handler 在写响应时执行, 不在 dispatch_request 的保护之内;
+// panic 时记录日志并返回 false, 调用方改用原来的响应
+fn call_guarded(handler: &HttpHandler, ctx: &mut Context, kind: &str) -> bool {
+    match panic::catch_unwind(AssertUnwindSafe(|| handler(ctx))) {
+        Ok(()) => true,
+        Err(payload) => {
+            log::error(&format!(
+                "[{}] {} {} panicked: {}",
+                ctx.request.id(),
+                kind,
+                ctx.request.path,
+                panic_message(payload.as_ref())
+            ));
+            false
+        }
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, PartialEq, Clone)]
+pub enum HttpMethod {
+    GET,
+    POST,
+    PUT,
+    PATCH,
+    DELETE,
+    HEAD,
+    OPTIONS,
+    TRACE,
+    CONNECT,
+}
+impl HttpMethod {
+    /// 只接受大写的方法名
+    pub fn name_of(name: String) -> Option<HttpMethod> {
+        match name.as_str() {
+            "GET" => Some(HttpMethod::GET),
+            "POST" => Some(HttpMethod::POST),
+            "PUT" => Some(HttpMethod::PUT),
+            "PATCH" => Some(HttpMethod::PATCH),
+            "DELETE" => Some(HttpMethod::DELETE),
+            "HEAD" => Some(HttpMethod::HEAD),
+            "OPTIONS" => Some(HttpMethod::OPTIONS),
+            "TRACE" => Some(HttpMethod::TRACE),
+            "CONNECT" => Some(HttpMethod::CONNECT),
+            _ => None,
+        }
+    }
+}
+
+/// 请求带有请求体(Content-Length 大于 0 或有 Transfer-Encoding)时的处理方式, 见 HttpServer::body_policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyPolicy {
+    /// 读取到 HttpRequest::body
+    Read,
+    /// 读取后丢弃, 连接上的下一个请求仍能正确解析
+    Ignore,
+    /// 不读取, 返回 400 并关闭连接
+    Reject,
+}
+impl BodyPolicy {
+    /// GET、HEAD 的请求体没有约定的含义, 丢弃; TRACE 不允许带请求体; 其他方法读取
+    pub fn default_for(method: &HttpMethod) -> BodyPolicy {
+        match method {
+            HttpMethod::GET | HttpMethod::HEAD => BodyPolicy::Ignore,
+            HttpMethod::TRACE => BodyPolicy::Reject,
+            _ => BodyPolicy::Read,
+        }
+    }
+}
+
+enum Favicon {
+    Bytes(&'static [u8]),
+    File(String),
+}
+
+/// 默认工作线程数为 CPU 核数的倍数, 处理函数大多在等待 IO 而不是占用 CPU
+pub const IO_WORKER_MULTIPLIER: usize = 4;
+
+/// 服务器本体: 注册路由、中间件与内置功能后调用 run
+pub struct HttpServer {
+    address: String,
+    middlewares: Vec<Middleware>,
+    handlers: Vec<RequestMapping>,
+    // handlers 的路径索引
+    router: Router,
+    /// HttpResponse::view 的模板目录, run 启动时检查
+    pub view_root: Option<String>,
+    view_root_missing: bool,
+    missing_roots_as_empty: bool,
+    favicon: Option<Favicon>,
+    well_known_root: Option<String>,
+    connect_allow_list: Vec<String>,
+    max_tunnels: usize,
+    // 隧道线程不在线程池中, 单独计数
+    active_tunnels: Arc<AtomicUsize>,
+    embedded_mounts: Vec<(String, EmbeddedDir)>,
+    static_dirs: Vec<StaticDir>,
+    // (Host 模式, 配置)
+    virtual_hosts: Vec<(String, VirtualHost)>,
+    trace_enabled: bool,
+    // (路径模式, 名称, 值)
+    default_headers: Vec<(String, String, String)>,
+    static_fallbacks: Vec<(String, HttpHandler)>,
+    error_handlers: Vec<(u16, HttpHandler)>,
+    default_error_handler: Option<HttpHandler>,
+    etag_policies: Vec<(String, EtagPolicy)>,
+    etag_cache: EtagCache,
+    max_response_body: Option<u64>,
+    max_request_body: Option<u64>,
+    max_response_time: Option<Duration>,
+    keep_alive: bool,
+    keep_alive_timeout: Duration,
+    max_keep_alive_requests: Option<usize>,
+    allowed_hosts: Vec<String>,
+    after_response_hooks: Vec<AfterResponseHook>,
+    cors: Option<CorsPolicy>,
+    error_renderer: Option<ErrorRenderer>,
+    access_log: bool,
+    admin_address: Option<String>,
+    config_file: Option<String>,
+    maintenance: AtomicBool,
+    active_connections: AtomicUsize,
+    requests: AtomicU64,
+    // 所有请求排队时间之和, 微秒
+    queue_wait_micros: AtomicU64,
+    // 0 为不限制, 可以由 watch_config 在运行期间修改
+    max_connections_per_ip: AtomicUsize,
+    // 已接受(含排队中)的连接数, 为 0 时移除
+    connections_per_ip: Mutex<HashMap<IpAddr, usize>>,
+    spool: Option<SpoolConfig>,
+    #[cfg(feature = "tls")]
+    tls: Option<transport::tls::ReloadableConfig>,
+    #[cfg(feature = "compression")]
+    compress_min_size: Option<u64>,
+    #[cfg(feature = "compression")]
+    compressed_files: compression::FileCache,
+    body_policies: Vec<(HttpMethod, BodyPolicy)>,
+    current_thread: bool,
+    worker_threads: Option<usize>,
+    handler_threads: Option<usize>,
+    shutdown: Arc<ShutdownState>,
+    shutdown_timeout: Option<Duration>,
+    shutdown_on_signals: bool,
+    reload_on_sighup: bool,
+}
+impl HttpServer {
+    pub fn new(address: String) -> HttpServer {
+        HttpServer {
+            address,
+            middlewares: Vec::new(),
+            handlers: Vec::new(),
+            router: Router::default(),
+            view_root: None,
+            view_root_missing: false,
+            missing_roots_as_empty: false,
+            favicon: None,
+            well_known_root: None,
+            connect_allow_list: Vec::new(),
+            max_tunnels: DEFAULT_MAX_TUNNELS,
+            active_tunnels: Arc::default(),
+            embedded_mounts: Vec::new(),
+            static_dirs: Vec::new(),
+            virtual_hosts: Vec::new(),
+            trace_enabled: false,
+            default_headers: Vec::new(),
+            static_fallbacks: Vec::new(),
+            error_handlers: Vec::new(),
+            default_error_handler: None,
+            etag_policies: Vec::new(),
+            etag_cache: EtagCache::new(etag::DEFAULT_ETAG_CACHE_ENTRIES),
+            max_response_body: None,
+            max_request_body: Some(DEFAULT_MAX_REQUEST_BODY),
+            max_response_time: None,
+            keep_alive: true,
+            keep_alive_timeout: Duration::from_secs(5),
+            max_keep_alive_requests: None,
+            allowed_hosts: Vec::new(),
+            after_response_hooks: Vec::new(),
+            cors: None,
+            error_renderer: None,
+            access_log: false,
+            admin_address: None,
+            config_file: None,
+            maintenance: AtomicBool::new(false),
+            active_connections: AtomicUsize::new(0),
+            requests: AtomicU64::new(0),
+            queue_wait_micros: AtomicU64::new(0),
+            max_connections_per_ip: AtomicUsize::new(0),
+            connections_per_ip: Mutex::new(HashMap::new()),
+            spool: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+            #[cfg(feature = "compression")]
+            compress_min_size: None,
+            #[cfg(feature = "compression")]
+            compressed_files: compression::FileCache::new(compression::DEFAULT_FILE_CACHE_BYTES),
+            body_policies: Vec::new(),
+            current_thread: false,
+            worker_threads: None,
+            handler_threads: None,
+            shutdown: Arc::default(),
+            shutdown_timeout: None,
+            shutdown_on_signals: false,
+            reload_on_sighup: false,
+        }
+    }
+    pub fn add_middleware(&mut self, middleware: Middleware) {
+        self.middlewares.push(middleware)
+    }
+    pub fn add_handler<F>(&mut self, method: HttpMethod, path: String, handler: F) -> &mut RequestMapping
+    where
+        F: Fn(&mut Context) + Send + Sync + 'static,
+    {
+        self.add_handler_for(&[method], path, handler)
+    }
+    /// 一个 handler 处理多个方法, 例如表单页 GET 展示, POST 提交
+    ///
+    /// 与已注册的路由模式相同且方法有交集时 panic
+    pub fn add_handler_for<F>(&mut self, methods: &[HttpMethod], path: String, handler: F) -> &mut RequestMapping
+    where
+        F: Fn(&mut Context) + Send + Sync + 'static,
+    {
+        // 同样的模式且方法有交集时, 后注册的永远不会被分发到
+        let normalized = normalize(&path);
+        if let Some(existing) = self.handlers.iter().find(|m| {
+            normalize(&m.path) == normalized
+                && (m.methods.is_empty() == methods.is_empty())
+                && (methods.is_empty() || methods.iter().any(|method| m.methods.contains(method)))
+        }) {
+            panic!(
+                "route conflict: {:?} {} is already registered as {:?} {}",
+                methods, path, existing.methods, existing.path
+            );
+        }
+        self.router.insert(&path, self.handlers.len());
+        self.handlers.push(RequestMapping {
+            methods: methods.to_vec(),
+            handler: Arc::new(handler),
+            path,
+            timeout: None,
+            timeout_threads: TimeoutThreads { limit: DEFAULT_TIMEOUT_THREADS, running: Arc::new(AtomicUsize::new(0)) },
+            consumes: Vec::new(),
+            produces: Vec::new(),
+            bulkhead: None,
+            cors: None,
+        });
+        self.handlers.last_mut().unwrap()
+    }
+    pub fn add_any_method_handler<F>(&mut self, path: String, handler: F) -> &mut RequestMapping
+    where
+        F: Fn(&mut Context) + Send + Sync + 'static,
+    {
+        self.add_handler_for(&[], path, handler)
+    }
+    /// 返回 Result 的处理函数, Err 由 error_renderer 转换成响应, 处理函数中可以用 ? 传播错误
+    ///
+    /// ```no_run
+    /// use rustbook_httpserver::{HttpError, HttpMethod, HttpResponse, HttpServer};
+    ///
+    /// let mut server = HttpServer::new("127.0.0.1:8080".into());
+    /// server.add_try_handler(HttpMethod::GET, "/users/:id".into(), |ctx| {
+    ///     let id: u64 = ctx
+    ///         .request
+    ///         .path_param("id")
+    ///         .and_then(|id| id.parse().ok())
+    ///         .ok_or_else(|| HttpError::bad_request("invalid id"))?;
+    ///     let name = std::fs::read_to_string(format!("./users/{}", id))?;
+    ///     Ok(HttpResponse::json(format!(r#"{{"name": "{}"}}"#, name.trim())))
+    /// });
+    /// ```
+    pub fn add_try_handler<F>(&mut self, method: HttpMethod, path: String, handler: F) -> &mut RequestMapping
+    where
+        F: Fn(&mut Context) -> Result<HttpResponse, HttpError> + Send + Sync + 'static,
+    {
+        self.add_try_handler_for(&[method], path, handler)
+    }
+    pub fn add_try_handler_for<F>(&mut self, methods: &[HttpMethod], path: String, handler: F) -> &mut RequestMapping
+    where
+        F: Fn(&mut Context) -> Result<HttpResponse, HttpError> + Send + Sync + 'static,
+    {
+        self.add_handler_for(methods, path, move |ctx| match handler(ctx) {
+            Ok(response) => ctx.set_response(response),
+            Err(error) => ctx.error = Some(error),
+        })
+    }
+    /// 自定义 add_try_handler 返回的错误的响应, 如统一的 JSON 错误格式
+    ///
+    /// 默认只设置状态码, 再按 set_error_handler 渲染错误页; 没有错误页时 4xx 以纯文本返回 message,
+    /// 5xx 只返回状态码(message 只记录到日志), 避免泄露内部细节
+    pub fn error_renderer<F>(&mut self, render: F)
+    where
+        F: Fn(&Context, &HttpError) -> HttpResponse + Send + Sync + 'static,
+    {
+        self.error_renderer = Some(Box::new(render));
+    }
+    /// 例如 favicon_bytes(include_bytes!("../static/favicon.ico"))
+    pub fn favicon_bytes(&mut self, bytes: &'static [u8]) {
+        self.favicon = Some(Favicon::Bytes(bytes));
+    }
+    pub fn favicon_file(&mut self, path: String) {
+        self.favicon = Some(Favicon::File(path));
+    }
+    /// 将 /.well-known/** 映射到目录, 如 security.txt, acme-challenge
+    pub fn well_known_dir(&mut self, dir: String) {
+        self.well_known_root = Some(dir);
+    }
+
+    /// 例如 mount_embedded("/assets".into(), embedded_dir!("../static", ["index.html"]))
+    pub fn mount_embedded(&mut self, prefix: String, dir: EmbeddedDir) {
+        self.embedded_mounts.push((prefix.trim_end_matches('/').to_string(), dir));
+    }
+    /// 将 url_prefix 下的 GET/HEAD 请求映射到 fs_root 下的文件, 如 serve_dir("/static".into(), "./static".into())
+    ///
+    /// 路径先解码再解析, 指向 fs_root 之外(包括经过符号链接)的请求返回 404; 目录返回其中的 index.html,
+    /// 没有时可以用 with_listing(true) 列出目录内容
+    pub fn serve_dir(&mut self, url_prefix: String, fs_root: String) -> &mut StaticDir {
+        self.static_dirs.push(StaticDir::new(&url_prefix, &fs_root));
+        self.static_dirs.last_mut().unwrap()
+    }
+    /// 默认 run 启动时 view_root 与 serve_dir 的目录不存在或不可读则返回 Error::Config;
+    /// 开启后不存在的目录只在启动时警告一次, 其下的请求直接 404
+    pub fn missing_roots_as_empty(&mut self, enabled: bool) {
+        self.missing_roots_as_empty = enabled;
+    }
+    /// Host 为 host 的请求使用 vhost 中的 view_root、静态目录与错误页, 如 "blog.example.com", "*.example.com"
+    ///
+    /// 精确的主机名优先于通配, 都不匹配时使用全局配置
+    pub fn virtual_host(&mut self, host: String, vhost: VirtualHost) {
+        self.virtual_hosts.push((host, vhost));
+    }
+    fn virtual_host_for(&self, request: &HttpRequest) -> Option<&VirtualHost> {
+        if self.virtual_hosts.is_empty() {
+            return None;
+        }
+        let host = request.header("Host").and_then(host_name)?;
+        self.virtual_hosts
+            .iter()
+            .filter(|(pattern, _)| is_host_match(pattern, &host))
+            .min_by_key(|(pattern, _)| pattern.starts_with('*'))
+            .map(|(_, vhost)| vhost)
+    }
+
+    /// 所有响应都带上的头, handler 已设置同名头时不覆盖
+    pub fn default_header(&mut self, name: &str, value: &str) {
+        self.default_header_for("/**".into(), name, value);
+    }
+    /// 只作用于匹配 path 的请求, 如 default_header_for("/static/**".into(), "Cache-Control", "max-age=3600")
+    ///
+    /// 与全局默认值同名时更具体的优先
+    pub fn default_header_for(&mut self, path: String, name: &str, value: &str) {
+        self.default_headers.push((path, name.to_string(), value.to_string()));
+    }
+
+    /// 匹配 path 的请求返回文件或 view 时使用的 ETag 策略, 多个模式匹配时取最具体的, 都不匹配时为 Weak
+    ///
+    /// 如 etag_policy("/static/**".into(), EtagPolicy::Strong); 嵌入资源总是使用强 ETag
+    pub fn etag_policy(&mut self, path: String, policy: EtagPolicy) {
+        self.etag_policies.push((path, policy));
+    }
+
+    /// 缓存强 ETag 的文件数, 默认 1024, 超出时淘汰最久没用过的; 0 表示不缓存
+    pub fn etag_cache_size(&mut self, entries: usize) {
+        self.etag_cache.set_capacity(entries);
+    }
+
+    /// HttpResponse::file 的文件不存在时改为调用 handler, 而不是直接 404
+    ///
+    /// 如 static_fallback("/static/**".into(), |ctx| ctx.set_response(HttpResponse::file("./static/404.html".into()).status_code(404))),
+    /// 也可以在 handler 中 ctx.forward 到动态路由; 多个模式匹配时取最具体的
+    pub fn static_fallback<F>(&mut self, path: String, handler: F)
+    where
+        F: Fn(&mut Context) + Send + Sync + 'static,
+    {
+        self.static_fallbacks.push((path, Arc::new(handler)));
+    }
+
+    /// 状态码为 status 且没有响应体的错误响应(路由 404、文件不存在、处理函数返回的 HttpError 等)改由 handler 生成,
+    /// 如品牌化的 HTML 错误页或统一的 JSON 错误格式
+    ///
+    /// handler 中 ctx.response 为原来的错误响应, ctx.error() 为处理函数返回的错误; 设置的响应可以是 file 或 view,
+    /// 状态码总是保持原样, 原响应上的 Allow、Retry-After 等头也会保留
+    pub fn set_error_handler<F>(&mut self, status_code: u16, handler: F)
+    where
+        F: Fn(&mut Context) + Send + Sync + 'static,
+    {
+        self.error_handlers.retain(|(status, _)| *status != status_code);
+        self.error_handlers.push((status_code, Arc::new(handler)));
+    }
+    /// 没有通过 set_error_handler 单独设置的 4xx、5xx 错误响应都由 handler 生成
+    pub fn set_default_error_handler<F>(&mut self, handler: F)
+    where
+        F: Fn(&mut Context) + Send + Sync + 'static,
+    {
+        self.default_error_handler = Some(Arc::new(handler));
+    }
+
+    /// 响应体超过 bytes 字节时: 大小已知的改为 500, 边写边发现的中断连接
+    pub fn max_response_body(&mut self, bytes: u64) {
+        self.max_response_body = Some(bytes);
+    }
+    /// 请求体(chunked 时为解码后的)超过 bytes 字节时回复 413 并关闭连接, 默认 10 MiB, 为 None 时不限制
+    ///
+    /// 开启 spool_request_body 接收大文件时通常需要一并调大
+    pub fn max_request_body(&mut self, bytes: Option<u64>) {
+        self.max_request_body = bytes;
+    }
+    /// 写出一个响应(含响应头)的最长时间, 超时中断连接, 防止慢客户端或无限输出占住服务器
+    pub fn max_response_time(&mut self, time: Duration) {
+        self.max_response_time = Some(time);
+    }
+
+    /// 默认开启, 关闭后每个响应都带 Connection: close
+    pub fn keep_alive(&mut self, enabled: bool) {
+        self.keep_alive = enabled;
+    }
+    /// 等待请求数据(包括同一连接上的下一个请求)的最长时间, 默认 5 秒, 在 Keep-Alive: timeout=N 中告知客户端
+    pub fn keep_alive_timeout(&mut self, timeout: Duration) {
+        self.keep_alive_timeout = timeout;
+    }
+    /// 一个连接上最多处理的请求数, 最后一个响应带 Connection: close; 默认不限制, 设置后在 Keep-Alive 中带上 max=剩余次数
+    pub fn max_keep_alive_requests(&mut self, max: usize) {
+        self.max_keep_alive_requests = Some(max);
+    }
+
+    /// 服务器日志的格式, 进程内全局生效
+    pub fn log_format(&mut self, format: LogFormat) {
+        log::set_format(format);
+    }
+    /// 每个响应写出后记录一条访问日志: 请求 id、方法、路径、路由、状态码、耗时、排队时间与收发字节数
+    pub fn access_log(&mut self) {
+        self.access_log = true;
+    }
+    /// 同一个客户端 IP 同时最多 limit 个连接(包括排队等待工作线程的), 超出的直接返回 429 并关闭; 为 0 时不限制
+    pub fn max_connections_per_ip(&mut self, limit: usize) {
+        self.max_connections_per_ip.store(limit, atomic::Ordering::Relaxed);
+    }
+    /// 请求体超过 threshold 字节时写入 dir 下的临时文件, 请求结束后删除, 通过 HttpRequest::body_reader 读取
+    pub fn spool_request_body(&mut self, threshold: u64, dir: String) {
+        self.spool = Some(SpoolConfig {
+            threshold,
+            dir: PathBuf::from(dir),
+        });
+    }
+    /// 以 HTTPS 提供服务, 路由与中间件不变; cert_path 为 PEM 格式的证书链, key_path 为 PEM 格式的私钥
+    ///
+    /// 需要开启 tls feature; 证书或私钥无法读取、格式错误时返回 Error::Config. TLS 连接上不支持 CONNECT 隧道
+    #[cfg(feature = "tls")]
+    pub fn tls(&mut self, cert_path: String, key_path: String) -> Result<(), Error> {
+        let config = transport::tls::ReloadableConfig::load(cert_path.clone(), key_path.clone())
+            .map_err(|e| Error::Config(format!("{} / {}: {}", cert_path, key_path, e)))?;
+        self.tls = Some(config);
+        Ok(())
+    }
+    #[cfg(feature = "tls")]
+    fn is_tls(&self) -> bool {
+        self.tls.is_some()
+    }
+    #[cfg(not(feature = "tls"))]
+    fn is_tls(&self) -> bool {
+        false
+    }
+    // 配置了 TLS 时包装成 TLS 连接, 握手在第一次读取时进行
+    fn transport(&self, stream: TcpStream) -> io::Result<Box<dyn Transport>> {
+        #[cfg(feature = "tls")]
+        if let Some(config) = self.tls.as_ref() {
+            return Ok(Box::new(transport::tls::accept(&config.current(), stream)?));
+        }
+        Ok(Box::new(stream))
+    }
+    /// 按 Accept-Encoding 以 gzip 或 deflate 压缩不小于 min_size 字节的文本类响应(html、css、js、json、xml、svg 等),
+    /// 对 body、view、file 与流式响应都有效, 并在 Vary 中加上 Accept-Encoding
+    ///
+    /// 需要开启 compression feature; 压缩后的响应不支持 Range, 强 ETag 改为弱 ETag;
+    /// 请求或响应带 Cache-Control: no-transform 时不压缩
+    #[cfg(feature = "compression")]
+    pub fn compress_responses(&mut self, min_size: u64) {
+        self.compress_min_size = Some(min_size);
+    }
+    /// 压缩后的文件缓存的总字节数, 默认 16 MiB, 超出时淘汰最久没用过的; 超过它 1/8 的文件不缓存, 0 表示不缓存
+    ///
+    /// 文件的修改时间或大小变化后重新压缩
+    #[cfg(feature = "compression")]
+    pub fn compressed_file_cache(&mut self, max_bytes: u64) {
+        self.compressed_files.set_max_bytes(max_bytes);
+    }
+    #[cfg(feature = "compression")]
+    fn compress(&self, request: &HttpRequest, response: &mut HttpResponse, body: ResponseBody) -> io::Result<ResponseBody> {
+        let Some(min_size) = self.compress_min_size else {
+            return Ok(body);
+        };
+        let eligible = response.status_code == 200
+            && response.header("Content-Encoding").is_none()
+            && response.header("Content-Type").is_some_and(compression::is_compressible)
+            && !compression::is_no_transform(response.header("Cache-Control"))
+            && !compression::is_no_transform(request.header("Cache-Control"));
+        if !eligible {
+            return Ok(body);
+        }
+        response.merge_vary("Accept-Encoding");
+        let Some(encoding) = compression::negotiate(request) else {
+            return Ok(body);
+        };
+        let len = match &body {
+            ResponseBody::Memory => Some(response.memory_body().len() as u64),
+            ResponseBody::File(file, _) => file.metadata().ok().map(|m| m.len()),
+            ResponseBody::Stream(_) => None,
+            ResponseBody::Empty => return Ok(body),
+        };
+        if len.is_some_and(|len| len < min_size) {
+            return Ok(body);
+        }
+        let body = match body {
+            ResponseBody::Memory => {
+                response.body = Some(compression::compress(response.memory_body(), encoding)?);
+                ResponseBody::Memory
+            }
+            // HttpResponse::file 的文件(包括 serve_dir)较小时使用缓存的压缩结果
+            ResponseBody::File(mut file, _)
+                if let Some(path) = response.file.clone()
+                    && let Ok(metadata) = file.metadata()
+                    && self.compressed_files.is_cacheable(metadata.len()) =>
+            {
+                let data = self.compressed_files.get_or_compress(Path::new(&path), &metadata, &mut file, encoding)?;
+                response.body = Some(data.to_vec());
+                ResponseBody::Memory
+            }
+            ResponseBody::File(mut file, _) => ResponseBody::Stream(StreamBody::new(move |out| {
+                let mut encoder = compression::Encoder::new(out, encoding);
+                io::copy(&mut file, &mut encoder)?;
+                encoder.finish().map(|_| ())
+            })),
+            ResponseBody::Stream(stream) => {
+                let Some(producer) = stream.take() else {
+                    return Ok(ResponseBody::Stream(stream));
+                };
+                ResponseBody::Stream(StreamBody::new(move |out| {
+                    let mut encoder = compression::Encoder::new(out, encoding);
+                    producer(&mut encoder)?;
+                    encoder.finish().map(|_| ())
+                }))
+            }
+            ResponseBody::Empty => ResponseBody::Empty,
+        };
+        compression::mark_encoded(response, encoding);
+        Ok(body)
+    }
+    #[cfg(not(feature = "compression"))]
+    fn compress(&self, _request: &HttpRequest, _response: &mut HttpResponse, body: ResponseBody) -> io::Result<ResponseBody> {
+        Ok(body)
+    }
+    /// 覆盖 method 的请求体处理方式, 默认见 BodyPolicy::default_for
+    pub fn body_policy(&mut self, method: HttpMethod, policy: BodyPolicy) {
+        self.body_policies.retain(|(m, _)| *m != method);
+        self.body_policies.push((method, policy));
+    }
+    /// 工作线程数, 默认 CPU 核数的 IO_WORKER_MULTIPLIER 倍; 为 0 时 panic
+    ///
+    /// 每个 keep-alive 连接在关闭前一直占用一个工作线程, 处理函数多为阻塞 IO 时应大于核数
+    pub fn worker_threads(&mut self, size: usize) {
+        assert!(size > 0);
+        self.worker_threads = Some(size);
+    }
+    /// 把处理函数放到单独的 size 个线程上执行, 为 0 时 panic
+    ///
+    /// 默认一个工作线程负责连接上的全部工作, 慢的处理函数会占住线程, 连带其他连接的请求无法被读取;
+    /// 开启后 worker_threads 个 IO 线程只负责读取与解析请求, 解析完交给 handler 线程执行路由并写出响应,
+    /// keep-alive 连接再回到 IO 线程等待下一个请求; current_thread 时不生效
+    pub fn handler_threads(&mut self, size: usize) {
+        assert!(size > 0);
+        self.handler_threads = Some(size);
+    }
+    /// 不创建工作线程, 在 run 的线程上逐个处理连接, 用于测试或不能创建线程的环境
+    ///
+    /// 一个连接处理完(包括 keep-alive 空闲超时)才会接受下一个; 设置了 timeout 的路由仍会为 handler 创建线程
+    pub fn current_thread(&mut self) {
+        self.current_thread = true;
+    }
+    /// 用于从其他线程停止 run: 不再接受新连接, 已有连接处理完当前请求后关闭, 等线程池中的任务结束后 run 返回
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle::new(Arc::clone(&self.shutdown))
+    }
+    /// 收到 SIGINT(Ctrl+C)或 SIGTERM 时按 shutdown_handle 的方式关闭, 只支持 unix
+    pub fn shutdown_on_signals(&mut self) {
+        self.shutdown_on_signals = true;
+    }
+    /// 收到 SIGHUP 时重新读取 watch_config 的配置文件与 tls 的证书和私钥, 只支持 unix
+    ///
+    /// 新连接使用新的证书, 已有连接不受影响; 读取失败时记录警告并保留原来的设置
+    pub fn reload_on_sighup(&mut self) {
+        self.reload_on_sighup = true;
+    }
+    /// 关闭时每个线程池最多等待进行中的请求 timeout, 之后 run 直接返回; 默认一直等待
+    ///
+    /// 空闲的 keep-alive 连接要等到 keep_alive_timeout 才会释放工作线程, 也受这个时间限制
+    pub fn shutdown_timeout(&mut self, timeout: Duration) {
+        self.shutdown_timeout = Some(timeout);
+    }
+    /// 在单独的端口上提供管理接口: 查看路由与统计、调整日志级别、切换维护模式、关闭服务器
+    ///
+    /// 没有鉴权, 只应绑定在本机或内网地址, 如 127.0.0.1:9090
+    pub fn admin_address(&mut self, address: String) {
+        self.admin_address = Some(address);
+    }
+    /// 运行期间监视配置文件, 修改后无需重启即可生效, 每行一个 key = value, # 开头为注释
+    ///
+    /// 支持 log_level(info/warn/error)、log_format(text/json)、maintenance(true/false)、
+    /// max_connections_per_ip(0 为不限制); 有任何一行无法识别时整个文件不生效,
+    /// worker_threads 等需要重启的设置只记录警告, 请在代码中设置
+    pub fn watch_config(&mut self, path: String) {
+        self.config_file = Some(path);
+    }
+
+    /// 响应写出后调用, 此时 ctx.response 为实际发送的响应, 可用于访问日志与指标
+    pub fn after_response<F>(&mut self, hook: F)
+    where
+        F: Fn(&Context) + Send + Sync + 'static,
+    {
+        self.after_response_hooks.push(Box::new(hook));
+    }
+
+    /// 允许的 Host(不含端口), 如 "example.com", "*.example.com"
+    ///
+    /// 列表为空时不检查; 否则缺少或格式错误的 Host 返回 400, 不在列表中的返回 421, 防止 DNS rebinding
+    pub fn allow_host(&mut self, host: String) {
+        self.allowed_hosts.push(host);
+    }
+
+    /// 全局跨域策略, 路由可以用 RequestMapping::cors 覆盖; 没有匹配到路由的请求不处理
+    ///
+    /// 预检请求按 Access-Control-Request-Method 找到目标路由, 以其策略直接回复 204 或 403, 不经过中间件
+    pub fn cors(&mut self, policy: CorsPolicy) {
+        self.cors = Some(policy);
+    }
+
+    /// TRACE 默认返回 405, 开启后回显请求头(不含认证信息)
+    pub fn enable_trace(&mut self, enabled: bool) {
+        self.trace_enabled = enabled;
+    }
+
+    /// 允许 CONNECT 隧道的目标, 如 "example.com:443", "*.example.com:443", "localhost:*"
+    ///
+    /// 列表为空时 CONNECT 一律 405
+    pub fn allow_connect(&mut self, target: String) {
+        self.connect_allow_list.push(target);
+    }
+    /// 同时存在的 CONNECT 隧道数上限, 默认 256, 超出时回复 503
+    ///
+    /// 每个隧道在线程池之外占用两个线程, 直到任意一端关闭
+    pub fn max_tunnels(&mut self, limit: usize) {
+        self.max_tunnels = limit;
+    }
+
+    // 没有匹配到用户路由时的内置处理
+    fn builtin_response(&self, request: &HttpRequest) -> Option<HttpResponse> {
+        if request.method != HttpMethod::GET && request.method != HttpMethod::HEAD {
+            return None;
+        }
+        if request.path == "/favicon.ico" {
+            return match self.favicon.as_ref()? {
+                Favicon::Bytes(bytes) => Some(HttpResponse::bytes(
+                    get_content_type("favicon.ico").into(),
+                    bytes.to_vec(),
+                )),
+                Favicon::File(path) => Some(HttpResponse::file(path.clone())),
+            };
+        }
+        for (prefix, dir) in self.embedded_mounts.iter() {
+            let Some(target) = request.path.strip_prefix(prefix.as_str()) else {
+                continue;
+            };
+            if !target.is_empty() && !target.starts_with('/') {
+                continue;
+            }
+            let Some(asset) = dir.get(target) else {
+                return Some(HttpResponse::new(404));
+            };
+            // 304 与 Range 由 write_response 按 ETag 处理
+            return Some(
+                HttpResponse::bytes(asset.content_type.into(), asset.bytes.to_vec())
+                    .add_header("ETag".into(), asset.etag.clone()),
+            );
+        }
+        if let Some((dir, target)) = self.static_dir_for(request) {
+            return Some(dir.response(request, target));
+        }
+        if let Some(root) = self.well_known_root.as_ref()
+            && let Some(target) = request.path.strip_prefix("/.well-known/")
+        {
+            // 拒绝 .. 之类的路径穿越
+            if target.is_empty() || target.split('/').any(|seg| seg.is_empty() || seg.starts_with('.')) {
+                return Some(HttpResponse::new(404));
+            }
+            let path_buf = Path::new(root).join(target);
+            return Some(HttpResponse::file(String::from(path_buf.to_str()?)));
+        }
+        None
+    }
+
+    /// 绑定地址并阻塞处理请求, 每个连接交给线程池处理; 通过 shutdown_handle 或信号关闭后返回 Ok
+    ///
+    /// 地址或管理端口无法绑定时返回 Error::Bind, 目录配置有误时返回 Error::Config
+    pub fn run(mut self) -> Result<(), Error> {
+        self.check_roots()?;
+        let listener = TcpListener::bind(&self.address).map_err(Error::Bind)?;
+        // 管理端口也在开始服务前绑定, 失败时同样返回 Error::Bind
+        let admin_listener = match self.admin_address.as_ref() {
+            Some(address) => Some(TcpListener::bind(address).map_err(Error::Bind)?),
+            None => None,
+        };
+        self.serve(listener, admin_listener);
+        Ok(())
+    }
+    fn serve(self, listener: TcpListener, admin_listener: Option<TcpListener>) {
+        if let Ok(address) = listener.local_addr() {
+            self.shutdown.bound(address);
+        }
+        let mut pools = if self.current_thread {
+            Pools {
+                io: ThreadPool::new_current_thread(),
+                handler: None,
+            }
+        } else {
+            Pools {
+                io: ThreadPool::new(self.pool_size()),
+                handler: self.handler_threads.map(ThreadPool::new),
+            }
+        };
+        if let Some(timeout) = self.shutdown_timeout {
+            pools.io.shutdown_timeout(timeout);
+            if let Some(handler) = pools.handler.as_mut() {
+                handler.shutdown_timeout(timeout);
+            }
+        }
+        // 任务只持有 Weak, 关闭时这里能取回唯一的所有权, 线程池在当前线程 drop
+        let mut pools = Arc::new(pools);
+        let signals = self
+            .shutdown_on_signals
+            .then(|| shutdown::on_signals(self.shutdown_handle()));
+        let hangup = self.reload_on_sighup.then(shutdown::on_hangup);
+        let server = Arc::new(self);
+        let admin = admin_listener.map(|listener| admin::spawn(listener, Arc::clone(&server), Arc::downgrade(&pools)));
+        let reload = (server.config_file.is_some() || server.reload_on_sighup)
+            .then(|| reload::spawn(server.config_file.clone(), Arc::clone(&server)));
+        // 阻塞在 accept 之前检查, 绑定之前就调用 shutdown 时没有连接来唤醒 accept
+        while !server.shutdown.is_requested() {
+            let Ok((stream, _)) = listener.accept() else {
+                continue;
+            };
+            // shutdown 发起的唤醒连接
+            if server.shutdown.is_requested() {
+                break;
+            }
+            let Some(permit) = IpPermit::acquire(&server, &stream) else {
+                server.reject_connection(stream, 429);
+                continue;
+            };
+            // 任务没能交给线程池时 connection 已随任务一起 drop, 用复制的句柄回复 503
+            let rejected = stream.try_clone();
+            let Some(connection) = Connection::new(Arc::clone(&server), stream, permit) else {
+                continue;
+            };
+            let job_pools = Arc::downgrade(&pools);
+            if let Err(e) = pools.io.execute(move || connection.run(job_pools)) {
+                log::error(&format!("execute failed: {}", e));
+                if let Ok(stream) = rejected {
+                    server.reject_connection(stream, 503);
+                }
+            }
+        }
+        drop(listener);
+        // 恢复原来的信号处理方式, 等待期间再次收到信号时按原来的方式处理(通常是直接退出)
+        drop(signals);
+        drop(hangup);
+        if let Some(admin) = admin {
+            admin.stop();
+        }
+        if let Some(reload) = reload {
+            reload.thread().unpark();
+            let _ = reload.join();
+        }
+        // 任务中 upgrade 得到的 Arc 很快就会释放
+        let pools = loop {
+            match Arc::try_unwrap(pools) {
+                Ok(pools) => break pools,
+                Err(shared) => {
+                    pools = shared;
+                    thread::sleep(Duration::from_millis(10));
+                }
+            }
+        };
+        log::info("waiting for in-flight requests");
+        drop(pools);
+        log::info("server stopped");
+    }
+    // 启动时检查一次, 而不是每个请求打开文件失败时都记录错误
+    fn check_roots(&mut self) -> Result<(), Error> {
+        let missing_as_empty = self.missing_roots_as_empty;
+        if let Some(root) = self.view_root.as_ref() {
+            self.view_root_missing = static_dir::check_root("view_root", root, missing_as_empty)?.is_none();
+        }
+        for dir in self.static_dirs.iter_mut() {
+            dir.check(missing_as_empty)?;
+        }
+        for (host, vhost) in self.virtual_hosts.iter_mut() {
+            if let Some(root) = vhost.view_root.as_ref() {
+                let kind = format!("view_root of {}", host);
+                vhost.view_root_missing = static_dir::check_root(&kind, root, missing_as_empty)?.is_none();
+            }
+            for dir in vhost.static_dirs.iter_mut() {
+                dir.check(missing_as_empty)?;
+            }
+        }
+        Ok(())
+    }
+    fn pool_size(&self) -> usize {
+        self.worker_threads.unwrap_or_else(|| {
+            let cpus = thread::available_parallelism().map_or(1, |n| n.get());
+            cpus * IO_WORKER_MULTIPLIER
+        })
+    }
+    // buffered 为读请求头时一并读入缓冲区的数据, 客户端可能不等 200 就开始发送(如 TLS ClientHello)
+    fn handle_connect(&self, mut transport: &mut dyn Transport, request: &HttpRequest, buffered: &[u8]) {
+        let target = request.path.as_str();
+        let version = response_version(request);
+        // 隧道要在 TLS 与上游之间逐条转发记录, 不支持
+        if transport.is_tls() {
+            let _ = self.write_response_line_header(&mut transport, version, &HttpResponse::new(405));
+            return;
+        }
+        let Ok(mut stream) = transport.tcp().try_clone() else {
+            return;
+        };
+        if self.connect_allow_list.is_empty() {
+            let _ = self.write_response_line_header(&mut stream, version, &HttpResponse::new(405));
+            return;
+        }
+        if !self
+            .connect_allow_list
+            .iter()
+            .any(|pattern| is_connect_target_match(pattern, target))
+        {
+            log::warn(&format!("CONNECT {} denied", target));
+            let _ = self.write_response_line_header(&mut stream, version, &HttpResponse::new(403));
+            return;
+        }
+        let Some(permit) = TunnelPermit::acquire(&self.active_tunnels, self.max_tunnels) else {
+            log::warn(&format!("CONNECT {} rejected, too many tunnels", target));
+            let _ = self.write_response_line_header(&mut stream, version, &HttpResponse::new(503));
+            return;
+        };
+        let mut upstream = match TcpStream::connect(target) {
+            Ok(upstream) => upstream,
+            Err(e) => {
+                log::warn(&format!("CONNECT {} failed: {}", target, e));
+                let _ = self.write_response_line_header(&mut stream, version, &HttpResponse::new(502));
+                return;
+            }
+        };
+        if self.write_response_line_header(&mut stream, version, &HttpResponse::new(200)).is_err()
+            || upstream.write_all(buffered).is_err()
+        {
+            return;
+        }
+        log::info(&format!("CONNECT {} established", target));
+        // 隧道可能持续很久, 不占用线程池; 线程结束时释放 permit
+        thread::spawn(move || {
+            tunnel(stream, upstream);
+            drop(permit);
+        });
+    }
+    fn is_method_match(&self, method: &HttpMethod, mapping: &RequestMapping) -> bool {
+        mapping.methods.is_empty() || mapping.methods.contains(method)
+    }
+    // 取最具体的匹配, 与注册顺序无关; 同样具体时先注册的优先, 限定了方法的优先于任意方法
+    // router 给出的候选路径都已匹配, 按注册顺序排列
+    fn find_handler(&self, method: &HttpMethod, path: &str) -> Option<&RequestMapping> {
+        let mut best: Option<&RequestMapping> = None;
+        let candidates = self.router.candidates(path);
+        for mapping in candidates
+            .into_iter()
+            .map(|i| &self.handlers[i])
+            .filter(|m| self.is_method_match(method, m))
+        {
+            let more_specific = best.is_none_or(|b| {
+                compare_specificity(&mapping.path, &b.path)
+                    .then_with(|| b.methods.is_empty().cmp(&mapping.methods.is_empty()))
+                    == Ordering::Greater
+            });
+            if more_specific {
+                best = Some(mapping);
+            }
+        }
+        best
+    }
+    fn dispatch_request(&self, request: HttpRequest, queue_wait: Duration) -> Context {
+        let mut ctx = Context::new(request);
+        ctx.queue_wait = queue_wait;
+        if let Some(status_code) = self.check_host(&ctx.request) {
+            ctx.set_response(HttpResponse::new(status_code));
+            return ctx;
+        }
+        if self.maintenance.load(atomic::Ordering::Relaxed) {
+            ctx.set_response(HttpResponse::new(503));
+            return ctx;
+        }
+        for _ in 0..=MAX_FORWARDS {
+            // handler 或中间件 panic 时返回 500, 不让工作线程跟着退出
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| self.dispatch(&mut ctx))) {
+                log::error(&format!(
+                    "[{}] {} panicked: {}",
+                    ctx.request.id(),
+                    ctx.request.path,
+                    panic_message(payload.as_ref())
+                ));
+                ctx.forward_to = None;
+                ctx.set_response(HttpResponse::new(500));
+                return ctx;
+            }
+            let Some(target) = ctx.forward_to.take() else {
+                return ctx;
+            };
+            log::info(&format!("forward {} -> {}", ctx.request.path, target));
+            ctx.response = None;
+            ctx.error = None;
+            ctx.request.set_target(&target);
+        }
+        log::error(&format!("too many forwards: {}", ctx.request.path));
+        ctx.set_response(HttpResponse::new(500));
+        ctx
+    }
+    fn dispatch(&self, ctx: &mut Context) {
+        if ctx.request.method == HttpMethod::TRACE {
+            ctx.set_response(self.trace_response(&ctx.request));
+            return;
+        }
+        if cors::is_preflight(&ctx.request)
+            && let Some(response) = self.preflight_response(ctx)
+        {
+            ctx.set_response(response);
+            return;
+        }
+        let handler = self.find_handler(&ctx.request.method, &ctx.request.path);
+        let matched_middlewares = self.middlewares_for(&ctx.request.method, &ctx.request.path);
+        match handler {
+            None => {
+                let response = self
+                    .builtin_response(&ctx.request)
+                    .unwrap_or_else(|| HttpResponse::new(404));
+                MiddlewareChain::respond(response, matched_middlewares).next(ctx);
+            }
+            Some(mapping) => {
+                self.dispatch_route(ctx, mapping, matched_middlewares);
+                if let Some(policy) = mapping.cors.as_ref().or(self.cors.as_ref())
+                    && let Some(response) = ctx.response.as_mut()
+                {
+                    policy.apply(&ctx.request, response);
+                }
+            }
+        }
+    }
+    fn dispatch_route(&self, ctx: &mut Context, mapping: &RequestMapping, matched_middlewares: Vec<&Middleware>) {
+        log::info(&format!("match {:?} {}", mapping.methods, mapping.path));
+        ctx.route = Some(mapping.path.clone());
+        ctx.request.path_params = capture_params(&mapping.path, &ctx.request.path).unwrap_or_default();
+        if let Some(status_code) = mapping.check_content_types(&ctx.request) {
+            let mut response = HttpResponse::new(status_code);
+            if status_code == 406 {
+                response.merge_vary("Accept");
+            }
+            MiddlewareChain::respond(response, matched_middlewares).next(ctx);
+            return;
+        }
+        // permit 持有到整个中间件链结束
+        let permit = mapping.bulkhead.as_ref().map(|b| b.acquire());
+        if let Some(None) = permit {
+            log::warn(&format!("concurrency limit reached: {}", mapping.path));
+            MiddlewareChain::respond(HttpResponse::new(503), matched_middlewares).next(ctx);
+            return;
+        }
+        let mut chain = MiddlewareChain::new(&mapping.handler, matched_middlewares);
+        chain.timeout = mapping.timeout.map(|timeout| (timeout, &mapping.timeout_threads));
+        chain.error_renderer = self.error_renderer.as_ref();
+        chain.next(ctx);
+        if !mapping.produces.is_empty()
+            && let Some(response) = ctx.response.as_mut()
+        {
+            response.merge_vary("Accept");
+        }
+    }
+    // 目标路由(按预检请求的方法查找)没有跨域策略时为 None, 按普通的 OPTIONS 请求处理
+    fn preflight_response(&self, ctx: &mut Context) -> Option<HttpResponse> {
+        let method = HttpMethod::name_of(ctx.request.header("Access-Control-Request-Method")?.to_string())?;
+        let mapping = self.find_handler(&method, &ctx.request.path)?;
+        let policy = mapping.cors.as_ref().or(self.cors.as_ref())?;
+        ctx.route = Some(mapping.path.clone());
+        Some(policy.preflight(&ctx.request, &mapping.methods))
+    }
+
+    fn check_host(&self, request: &HttpRequest) -> Option<u16> {
+        if self.allowed_hosts.is_empty() {
+            return None;
+        }
+        let Some(host) = request.header("Host").and_then(host_name) else {
+            return Some(400);
+        };
+        if self.allowed_hosts.iter().any(|pattern| is_host_match(pattern, &host)) {
+            return None;
+        }
+        log::warn(&format!("host not allowed: {}", host));
+        Some(421)
+    }
+
+    // 按 order 升序(小的在外层), order 相同时保持注册顺序
+    fn middlewares_for(&self, method: &HttpMethod, path: &str) -> Vec<&Middleware> {
+        let mut matched = self
+            .middlewares
+            .iter()
+            .filter(|m| m.method.as_ref().is_none_or(|m| m == method) && is_path_match(&m.path, path))
+            .collect::<Vec<&Middleware>>();
+        matched.sort_by_key(|m| m.order);
+        matched
+    }
+    /// 调试用: 某个请求实际会经过的中间件, 由外到内
+    pub fn describe_middlewares(&self, method: &HttpMethod, path: &str) -> Vec<String> {
+        self.middlewares_for(method, path)
+            .iter()
+            .map(|m| {
+                let name = if m.name.is_empty() { "<unnamed>" } else { m.name.as_str() };
+                format!("{} order={} {:?} {}", name, m.order, m.method, m.path)
+            })
+            .collect()
+    }
+
+    fn trace_response(&self, request: &HttpRequest) -> HttpResponse {
+        if !self.trace_enabled {
+            return HttpResponse::new(405);
+        }
+        let mut head = if request.query_string.is_empty() {
+            format!("TRACE {} {}\r\n", request.path, request.version)
+        } else {
+            format!("TRACE {}?{} {}\r\n", request.path, request.query_string, request.version)
+        };
+        for (key, value) in request.headers.iter() {
+            if TRACE_EXCLUDED_HEADERS.iter().any(|h| h.eq_ignore_ascii_case(key)) {
+                continue;
+            }
+            head.push_str(&format!("{}: {}\r\n", key, value));
+        }
+        HttpResponse::new(200)
+            .add_header("Content-Type".into(), "message/http".into())
+            .body(head)
+    }
+
+    // 返回连接能否继续用于下一个请求; 写出后 ctx.response 为实际发送的响应(如文件不存在时的 404)
+    // remaining 为连接上还能处理的请求数(含当前请求), 不限制时为 None
+    fn handler_response(&self, stream: &mut dyn Transport, ctx: &mut Context, remaining: Option<usize>) -> bool {
+        let Some(response) = ctx.response.as_mut() else {
+            return false;
+        };
+        let deadline = self.max_response_time.map(|time| Instant::now() + time);
+        let mut counting = CountingStream { stream, written: 0 };
+        let result = self.write_response(&mut counting, &ctx.request, ctx.error.as_ref(), response, deadline, remaining);
+        ctx.bytes_written = counting.written;
+        match result {
+            Ok(keep_alive) => keep_alive,
+            Err(e) => {
+                log::warn(&format!("response aborted: {}: {}", e, ctx.request.path));
+                let _ = stream.tcp().shutdown(Shutdown::Both);
+                false
+            }
+        }
+    }
+    fn write_response(
+        &self,
+        stream: &mut CountingStream,
+        request: &HttpRequest,
+        error: Option<&HttpError>,
+        response: &mut HttpResponse,
+        deadline: Option<Instant>,
+        remaining: Option<usize>,
+    ) -> io::Result<bool> {
+        let version = response_version(request);
+        if let Some(deadline) = deadline {
+            stream.set_write_timeout(Some(remaining_time(deadline)?))?;
+        }
+        let (mut resolved, mut body) = self.open_body(request, mem::replace(response, HttpResponse::new(500)), true);
+        if matches!(body, ResponseBody::Empty)
+            && resolved.status_code >= 400
+            && let Some(page) = self.error_page(request, error, &resolved)
+        {
+            let status_code = resolved.status_code;
+            (resolved, body) = self.open_body(request, page, false);
+            // 错误页不参与条件请求, 文件缺失时也保持原来的状态码
+            resolved.status_code = status_code;
+            resolved.remove_header("ETag");
+        }
+        *response = resolved;
+        // 内存中的响应体(bytes、嵌入资源等)与文件一样支持条件请求与 Range
+        if matches!(body, ResponseBody::Memory)
+            && let Some(not_modified) = not_modified_response(request, response)
+        {
+            *response = not_modified;
+            body = ResponseBody::Empty;
+        }
+        body = self.compress(request, response, body)?;
+        if matches!(body, ResponseBody::Memory) {
+            range::apply_to_memory(request, response);
+        }
+        if let ResponseBody::File(file, len) = &mut body {
+            match range::apply_to_file(request, response, file)? {
+                RangeRequest::Full => {}
+                RangeRequest::Partial(start, end) => *len = Some(end - start + 1),
+                RangeRequest::Unsatisfiable => body = ResponseBody::Empty,
+            }
+        }
+        let body_len = match &body {
+            ResponseBody::Empty => Some(0),
+            ResponseBody::Memory => Some(response.memory_body().len() as u64),
+            ResponseBody::Stream(_) => None,
+            ResponseBody::File(_, Some(len)) => Some(*len),
+            ResponseBody::File(file, None) => file.metadata().ok().map(|m| m.len()),
+        };
+        if body_len.is_some_and(|len| self.is_body_too_large(request, len)) {
+            *response = HttpResponse::new(500);
+            body = ResponseBody::Empty;
+        }
+        self.apply_default_headers(request, response);
+
+        // 持久连接要求客户端能确定响应体在哪里结束
+        let framed = match body {
+            ResponseBody::Empty => {
+                if allows_body(response.status_code) {
+                    response.set_header("Content-Length", "0".into());
+                }
+                true
+            }
+            ResponseBody::Memory => {
+                let len = response.memory_body().len();
+                response.set_header("Content-Length", len.to_string());
+                true
+            }
+            // HTTP/1.0 不支持 chunked, 只能以关闭连接表示结束
+            ResponseBody::Stream(_) => {
+                if version == "HTTP/1.1" {
+                    response.remove_header("Content-Length");
+                    response.set_header("Transfer-Encoding", "chunked".into());
+                }
+                version == "HTTP/1.1"
+            }
+            // 长度取自文件元数据, 读取失败时只能以关闭连接表示结束
+            ResponseBody::File(..) => match body_len {
+                Some(len) => {
+                    response.set_header("Content-Length", len.to_string());
+                    true
+                }
+                None => {
+                    response.remove_header("Content-Length");
+                    false
+                }
+            },
+        };
+        let keep_alive = self.keep_alive
+            && !self.shutdown.is_requested()
+            && framed
+            && wants_keep_alive(request)
+            && remaining.is_none_or(|n| n > 1)
+            && !response.header("Connection").is_some_and(|c| c.eq_ignore_ascii_case("close"));
+        if !keep_alive {
+            response.set_header("Connection", "close".into());
+            response.remove_header("Keep-Alive");
+        } else {
+            if version == "HTTP/1.0" {
+                response.set_header("Connection", "keep-alive".into());
+            }
+            // 让客户端与代理知道空闲多久后连接会被关闭, 避免在即将关闭的连接上发送请求
+            let mut params = format!("timeout={}", self.keep_alive_timeout.as_secs());
+            if let Some(n) = remaining {
+                params.push_str(&format!(", max={}", n - 1));
+            }
+            response.set_header("Keep-Alive", params);
+        }
+
+        self.write_response_line_header(stream, version, response)?;
+        if request.method == HttpMethod::HEAD {
+            return Ok(keep_alive);
+        }
+        match body {
+            ResponseBody::Empty => {}
+            ResponseBody::Memory => self.body_writer(stream, deadline).write_all(response.memory_body())?,
+            ResponseBody::Stream(producer) => {
+                let Some(producer) = producer.take() else {
+                    return Err(io::Error::other("stream body already consumed"));
+                };
+                let mut writer = self.body_writer(stream, deadline);
+                if version == "HTTP/1.1" {
+                    let mut chunked = ChunkedWriter { inner: writer };
+                    producer(&mut chunked)?;
+                    chunked.finish()?;
+                } else {
+                    producer(&mut writer)?;
+                }
+            }
+            ResponseBody::File(file, len) => {
+                let mut writer = self.body_writer(stream, deadline);
+                io::copy(&mut file.take(len.unwrap_or(u64::MAX)), &mut writer)?;
+            }
+        }
+        Ok(keep_alive)
+    }
+
+    // view 与 file 在这里打开, 不存在时换成 404 或 fallback 的响应
+    // fallback 返回的文件也不存在时不再 fallback, 避免循环
+    fn open_body(&self, request: &HttpRequest, mut response: HttpResponse, use_fallback: bool) -> (HttpResponse, ResponseBody) {
+        if response.body.is_some() {
+            return (response, ResponseBody::Memory);
+        }
+        if let Some(stream) = response.stream.take() {
+            return (response, ResponseBody::Stream(stream));
+        }
+        if let Some(view) = response.view.clone() {
+            let (view_root, missing) = match self.virtual_host_for(request).filter(|vhost| vhost.view_root.is_some()) {
+                Some(vhost) => (vhost.view_root.as_ref(), vhost.view_root_missing),
+                None => (self.view_root.as_ref(), self.view_root_missing),
+            };
+            if missing {
+                return (not_found(response), ResponseBody::Empty);
+            }
+            let view_path = match view_root {
+                Some(root) => {
+                    Path::new(root).join(view)
+                }
+                None => PathBuf::from(view),
+            };
+            log::info(&format!("look for view: {:?}", view_path));
+            return match File::open(&view_path) {
+                Ok(file) => {
+                    self.add_file_validators(request, &mut response, &view_path, &file);
+                    if let Some(not_modified) = not_modified_response(request, &response) {
+                        return (not_modified, ResponseBody::Empty);
+                    }
+                    (response, ResponseBody::File(file, None))
+                }
+                Err(e) => {
+                    log::error(&format!("Error opening file: {} {:?}", e, view_path));
+                    (not_found(response), ResponseBody::Empty)
+                }
+            };
+        }
+        if let Some(file_path) = response.file.clone() {
+            return match File::open(&file_path) {
+                Ok(file) => {
+                    self.add_file_validators(request, &mut response, Path::new(&file_path), &file);
+                    if let Some(not_modified) = not_modified_response(request, &response) {
+                        return (not_modified, ResponseBody::Empty);
+                    }
+                    let content_type = get_content_type(static_dir::original_path(&response, &file_path));
+                    if let Some(headers) = response.headers.as_mut() {
+                        headers.insert("Content-Type".into(), content_type.into());
+                    }
+                    (response, ResponseBody::File(file, None))
+                }
+                Err(e) => {
+                    log::error(&format!("Error opening file: {} {:?}", e, file_path));
+                    if use_fallback && let Some(fallback) = self.static_fallback_response(request) {
+                        return self.open_body(request, fallback, false);
+                    }
+                    (not_found(response), ResponseBody::Empty)
+                }
+            };
+        }
+        (response, ResponseBody::Empty)
+    }
+
+    // 没有响应体的错误响应对应的错误页, 保持原来的状态码, 并补上错误页没有设置的原响应头
+    fn error_page(&self, request: &HttpRequest, error: Option<&HttpError>, response: &HttpResponse) -> Option<HttpResponse> {
+        // 虚拟主机上的错误页优先, 依次为 vhost 单独设置、vhost 默认、全局单独设置、全局默认
+        let status_code = response.status_code;
+        let handler = self
+            .virtual_host_for(request)
+            .and_then(|vhost| error_handler_for(&vhost.error_handlers, status_code).or(vhost.default_error_handler.as_ref()))
+            .or_else(|| error_handler_for(&self.error_handlers, status_code))
+            .or(self.default_error_handler.as_ref());
+        let Some(handler) = handler else {
+            let error = error.filter(|e| e.status_code == response.status_code && e.status_code < 500)?;
+            let mut page = response.clone();
+            page.set_header("Content-Type", "text/plain; charset=utf-8".into());
+            return Some(page.body(error.message.clone()));
+        };
+        let mut ctx = Context::new(request.clone());
+        ctx.error = error.cloned();
+        ctx.response = Some(response.clone());
+        if !call_guarded(handler, &mut ctx, "error page") {
+            return None;
+        }
+        let mut page = ctx.response?;
+        page.status_code = response.status_code;
+        for (name, value) in response.headers.iter().flatten() {
+            if page.header(name).is_none() {
+                page.set_header(name, value.clone());
+            }
+        }
+        Some(page)
+    }
+
+    // ETag 与 Last-Modified, handler 已设置时不覆盖
+    fn add_file_validators(&self, request: &HttpRequest, response: &mut HttpResponse, path: &Path, file: &File) {
+        if response.header("ETag").is_none()
+            && let Some(etag) = self.file_etag(request, path, file)
+        {
+            response.set_header("ETag", etag);
+        }
+        if response.header("Last-Modified").is_none()
+            && let Ok(modified) = file.metadata().and_then(|m| m.modified())
+        {
+            response.set_header("Last-Modified", http_date::format(modified));
+        }
+    }
+    // 请求路径所在的 serve_dir 挂载点(虚拟主机的优先)与去掉前缀后的路径
+    fn static_dir_for<'a>(&'a self, request: &'a HttpRequest) -> Option<(&'a StaticDir, &'a str)> {
+        let vhost_dirs = self.virtual_host_for(request).map(|vhost| vhost.static_dirs.as_slice());
+        vhost_dirs.unwrap_or_default().iter().chain(self.static_dirs.iter()).find_map(|dir| {
+            let target = request.path.strip_prefix(dir.prefix.as_str())?;
+            (target.is_empty() || target.starts_with('/')).then_some((dir, target))
+        })
+    }
+
+    // 按挂载点的 with_etag 或 etag_policy 计算文件的 ETag
+    fn file_etag(&self, request: &HttpRequest, path: &Path, file: &File) -> Option<String> {
+        let policy = self.static_dir_for(request).and_then(|(dir, _)| dir.etag).unwrap_or_else(|| {
+            self.etag_policies
+                .iter()
+                .filter(|(pattern, _)| is_path_match(pattern, &request.path))
+                .max_by(|a, b| compare_specificity(&a.0, &b.0))
+                .map_or(EtagPolicy::Weak, |(_, policy)| *policy)
+        });
+        let metadata = file.metadata().ok()?;
+        match policy {
+            EtagPolicy::Weak => Some(weak_etag(&metadata)),
+            EtagPolicy::Strong => self.etag_cache.strong_file_etag(path, &metadata).ok(),
+            EtagPolicy::Off => None,
+        }
+    }
+
+    // 大小已知的响应体超出上限时还没有写出任何内容, 可以改为 500
+    fn is_body_too_large(&self, request: &HttpRequest, len: u64) -> bool {
+        let too_large = self.max_response_body.is_some_and(|max| len > max);
+        if too_large {
+            log::error(&format!("response body of {} bytes exceeds limit: {}", len, request.path));
+        }
+        too_large
+    }
+    fn body_writer<'a, 'b>(&self, stream: &'a mut CountingStream<'b>, deadline: Option<Instant>) -> LimitedWriter<'a, 'b> {
+        LimitedWriter {
+            stream,
+            written: 0,
+            max_bytes: self.max_response_body,
+            deadline,
+        }
+    }
+
+    fn static_fallback_response(&self, request: &HttpRequest) -> Option<HttpResponse> {
+        let mut best: Option<&(String, HttpHandler)> = None;
+        for fallback in self.static_fallbacks.iter().filter(|(path, _)| is_path_match(path, &request.path)) {
+            if best.is_none_or(|b| compare_specificity(&fallback.0, &b.0) == Ordering::Greater) {
+                best = Some(fallback);
+            }
+        }
+        let (_, handler) = best?;
+        let mut ctx = Context::new(request.clone());
+        if !call_guarded(handler, &mut ctx, "static fallback") {
+            return None;
+        }
+        if let Some(target) = ctx.forward_to.take() {
+            ctx.request.set_target(&target);
+            ctx = self.dispatch_request(ctx.request, Duration::ZERO);
+        }
+        ctx.response
+    }
+    fn apply_default_headers(&self, request: &HttpRequest, response: &mut HttpResponse) {
+        let mut matched = self
+            .default_headers
+            .iter()
+            .filter(|(path, _, _)| is_path_match(path, &request.path))
+            .collect::<Vec<_>>();
+        matched.sort_by(|a, b| compare_specificity(&b.0, &a.0));
+        for (_, name, value) in matched {
+            if response.header(name).is_none() {
+                response
+                    .headers
+                    .get_or_insert_with(HashMap::new)
+                    .insert(name.clone(), value.clone());
+            }
+        }
+    }
+    // 在 acceptor 线程回复并关闭, 不占用工作线程; 还没有握手, TLS 连接上只能直接关闭
+    fn reject_connection(&self, mut stream: TcpStream, status_code: u16) {
+        if self.is_tls() {
+            return;
+        }
+        let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+        let response = HttpResponse::new(status_code)
+            .add_header("Content-Length".into(), "0".into())
+            .add_header("Connection".into(), "close".into());
+        let _ = self.write_response_line_header(&mut stream, "HTTP/1.1", &response);
+    }
+    fn write_response_line_header(&self, stream: &mut impl Write, version: &str, response:  &HttpResponse) -> io::Result<()> {
+        // 没有登记的状态码原因短语为空, 状态行仍以空格结尾
+        let message = match &response.reason {
+            Some((status_code, reason)) if *status_code == response.status_code => reason.as_str(),
+            _ => StatusCode::new(response.status_code)
+                .and_then(StatusCode::reason_phrase)
+                .unwrap_or_default(),
+        };
+        let response_line: String = format!("{} {} {}\r\n", version, response.status_code, message);
+
+        stream.write_all(response_line.as_bytes())?;
+        if let Some(ref headers) = response.headers {
+            for (key, value) in headers.iter() {
+                // 值来自请求时(如重定向的 Location)可能被注入 CR、LF, 整个响应头丢弃, 不能拼出额外的响应头或响应
+                if !is_valid_header(key, value) {
+                    log::warn(&format!("invalid response header dropped: {:?}: {:?}", key, value));
+                    continue;
+                }
+                let header_line = format!("{}: {}\r\n", key, value);
+                stream.write_all(header_line.as_bytes())?;
+            }
+        }
+        stream.write_all(b"\r\n")
+    }
+}
+enum ResponseBody {
+    Empty,
+    // HttpResponse 的 body
+    Memory,
+    Stream(StreamBody),
+    // Range 请求只发送从当前位置开始的部分字节, 否则为 None
+    File(File, Option<u64>),
+}
+
+// 文件不存在时的 404, 去掉原本为文件准备的响应头
+fn not_found(mut response: HttpResponse) -> HttpResponse {
+    response.status_code = 404;
+    if let Some(headers) = response.headers.as_mut() {
+        headers.remove("Content-Type");
+        headers.remove("Cache-Control");
+    }
+    response
+}
+
+// 名称须为 token, 值不能含 CR、LF、NUL(RFC 9110 5.5)
+fn is_valid_header(name: &str, value: &str) -> bool {
+    media_type::is_token(name) && !value.contains(['\r', '\n', '\0'])
+}
+
+// 1xx、204、304 不能带响应体, 也不发送 Content-Length
+fn allows_body(status_code: u16) -> bool {
+    !(100..200).contains(&status_code) && status_code != 204 && status_code != 304
+}
+
+// HTTP/1.1 默认保持连接, HTTP/1.0 需要显式 Connection: keep-alive
+fn wants_keep_alive(request: &HttpRequest) -> bool {
+    let connection = request.header("Connection").unwrap_or("");
+    let has_token = |token: &str| connection.split(',').any(|t| t.trim().eq_ignore_ascii_case(token));
+    if request.version == "HTTP/1.0" {
+        has_token("keep-alive")
+    } else {
+        !has_token("close")
+    }
+}
+
+// 接受连接时占用客户端 IP 的一个名额, 连接处理结束或任务未能执行时归还
+struct IpPermit {
+    server: Arc<HttpServer>,
+    ip: Option<IpAddr>,
+}
+
+impl IpPermit {
+    fn acquire(server: &Arc<HttpServer>, stream: &TcpStream) -> Option<IpPermit> {
+        // 运行期间从不限制改为限制时, 之前接受的连接不计入
+        let limit = server.max_connections_per_ip.load(atomic::Ordering::Relaxed);
+        let Some(ip) = stream.peer_addr().ok().filter(|_| limit > 0).map(|addr| addr.ip()) else {
+            return Some(IpPermit {
+                server: Arc::clone(server),
+                ip: None,
+            });
+        };
+        let mut connections = server.connections_per_ip.lock().unwrap();
+        let count = connections.entry(ip).or_insert(0);
+        if *count >= limit {
+            log::warn(&format!("too many connections from {}", ip));
+            return None;
+        }
+        *count += 1;
+        Some(IpPermit {
+            server: Arc::clone(server),
+            ip: Some(ip),
+        })
+    }
+}
+
+impl Drop for IpPermit {
+    fn drop(&mut self) {
+        let Some(ip) = self.ip else {
+            return;
+        };
+        let mut connections = self.server.connections_per_ip.lock().unwrap();
+        if let Some(count) = connections.get_mut(&ip) {
+            *count -= 1;
+            if *count == 0 {
+                connections.remove(&ip);
+            }
+        }
+    }
+}
+
+// panic! 的参数通常是 &str 或 String
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => payload.downcast_ref::<String>().map_or("<non-string panic>", |m| m.as_str()),
+    }
+}
+
+// run 使用的线程池, handler 为 None 时连接的全部工作都在 io 上完成
+struct Pools {
+    io: ThreadPool,
+    handler: Option<ThreadPool>,
+}
+
+// 一个客户端连接, 可以在线程池之间移交; 释放时(包括 CONNECT 隧道建立后)减少活动连接数
+struct Connection {
+    server: Arc<HttpServer>,
+    stream: Box<dyn Transport>,
+    reader: BufReader<Box<dyn Transport>>,
+    remote_addr: String,
+    // 最近一次交给线程池的时间, 与尚未计入请求的排队时间
+    queued_at: Instant,
+    queue_wait: Duration,
+    // 已交给 CONNECT 隧道, 不能再关闭 socket
+    tunnel: bool,
+    // 已处理的请求数, 用于 max_keep_alive_requests
+    served: usize,
+    _permit: IpPermit,
+}
+
+impl Connection {
+    fn new(server: Arc<HttpServer>, stream: TcpStream, permit: IpPermit) -> Option<Connection> {
+        let remote_addr = stream.peer_addr().ok()?;
+        let _ = stream.set_read_timeout(Some(server.keep_alive_timeout));
+        let stream = match server.transport(stream) {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::warn(&format!("connection from {} dropped: {}", remote_addr, e));
+                return None;
+            }
+        };
+        let read_half = stream.try_clone().ok()?;
+        server.active_connections.fetch_add(1, atomic::Ordering::Relaxed);
+        Some(Connection {
+            server,
+            stream,
+            reader: BufReader::new(read_half),
+            remote_addr: remote_addr.to_string(),
+            queued_at: Instant::now(),
+            queue_wait: Duration::ZERO,
+            tunnel: false,
+            served: 0,
+            _permit: permit,
+        })
+    }
+
+    // 同一个连接上依次处理多个请求, 直到任意一方要求关闭、连接断开或空闲超时;
+    // 有 handler 线程池时读到一个请求就移交过去, 由 handler 线程处理完再交回 io 线程池
+    // 关闭过程中 pools 已无法 upgrade, 读到的请求直接在当前线程处理
+    fn run(mut self, pools: Weak<Pools>) {
+        self.queue_wait += self.queued_at.elapsed();
+        while let Some((request, bytes_read)) = self.read_request() {
+            if let Some(shared) = pools.upgrade()
+                && let Some(handler) = shared.handler.as_ref()
+            {
+                self.queued_at = Instant::now();
+                // 任务没能交给 handler 线程池时 self 已随任务一起 drop, 用复制的句柄对已读到的请求回复 503
+                let (server, version) = (Arc::clone(&self.server), response_version(&request));
+                let rejected = self.stream.try_clone();
+                if let Err(e) = handler.execute(move || self.serve_then_read(request, bytes_read, pools)) {
+                    log::error(&format!("execute failed: {}", e));
+                    if let Ok(mut stream) = rejected {
+                        let response = HttpResponse::new(503)
+                            .add_header("Content-Length".into(), "0".into())
+                            .add_header("Connection".into(), "close".into());
+                        let _ = server.write_response_line_header(&mut stream, version, &response);
+                        stream.close();
+                    }
+                }
+                return;
+            }
+            if !self.serve(request, bytes_read) {
+                break;
+            }
+        }
+        self.close();
+    }
+
+    fn serve_then_read(mut self, request: HttpRequest, bytes_read: u64, pools: Weak<Pools>) {
+        self.queue_wait += self.queued_at.elapsed();
+        if !self.serve(request, bytes_read) {
+            self.close();
+            return;
+        }
+        let Some(shared) = pools.upgrade() else {
+            self.close();
+            return;
+        };
+        self.queued_at = Instant::now();
+        if let Err(e) = shared.io.execute(move || self.run(pools)) {
+            log::error(&format!("execute failed: {}", e));
+        }
+    }
+
+    // 格式错误、客户端关闭、空闲超时、已回复 505 或转为 CONNECT 隧道时为 None
+    fn read_request(&mut self) -> Option<(HttpRequest, u64)> {
+        let server = &self.server;
+        let mut counting = CountingReader {
+            inner: &mut self.reader,
+            read: 0,
+        };
+        let parsed = parse_http_request(
+            &mut counting,
+            self.remote_addr.clone(),
+            server.spool.as_ref(),
+            server.max_request_body,
+            &server.body_policies,
+        );
+        let bytes_read = counting.read;
+        let parsed = parsed.map(|mut request| {
+            request.secure = self.stream.is_tls();
+            request
+        });
+        match parsed {
+            Ok(request) if !is_supported_version(&request.version) => {
+                let response = HttpResponse::new(505).add_header("Connection".into(), "close".into());
+                let _ = server.write_response_line_header(&mut self.stream, "HTTP/1.1", &response);
+                None
+            }
+            // 请求体没有读取, 连接上剩下的数据无法解析, 只能关闭
+            Ok(request)
+                if request.has_body() && body_policy(&server.body_policies, &request.method) == BodyPolicy::Reject =>
+            {
+                log::warn(&format!("{:?} {} with body rejected", request.method, request.path));
+                let response = HttpResponse::new(400)
+                    .add_header("Content-Length".into(), "0".into())
+                    .add_header("Connection".into(), "close".into());
+                let _ = server.write_response_line_header(&mut self.stream, response_version(&request), &response);
+                None
+            }
+            Ok(request) if request.method == HttpMethod::CONNECT => {
+                let buffered = self.reader.buffer().to_vec();
+                server.handle_connect(self.stream.as_mut(), &request, &buffered);
+                self.tunnel = true;
+                None
+            }
+            Ok(request) => Some((request, bytes_read)),
+            // 请求格式错误时回复 400 再关闭, 连接断开或超时则直接关闭
+            // 请求头没有读完, 连接上剩下的数据无法解析, 回复后关闭
+            Err(Error::HeaderTooLarge) => {
+                log::warn(&format!("request header too large: {}", self.remote_addr));
+                self.reject_and_drain(431);
+                None
+            }
+            // 请求体没有读取, 同样只能回复后关闭
+            Err(Error::BodyTooLarge) => {
+                log::warn(&format!("request body too large: {}", self.remote_addr));
+                self.reject_and_drain(413);
+                None
+            }
+            Err(Error::Parse(reason)) => {
+                log::warn(&format!("{}: {}", reason, self.remote_addr));
+                self.reject_and_drain(400);
+                None
+            }
+            Err(_) => None,
+        }
+    }
+
+    // 请求没有读完时回复错误并关闭: 直接关闭时内核缓冲区中还有未读数据, 连接会以 RST 结束,
+    // 客户端往往只看到连接被重置而读不到响应; 先关闭写方向, 再丢弃有限的输入, 给客户端读取响应的时间
+    fn reject_and_drain(&mut self, status_code: u16) {
+        let response = HttpResponse::new(status_code)
+            .add_header("Content-Length".into(), "0".into())
+            .add_header("Connection".into(), "close".into());
+        if self
+            .server
+            .write_response_line_header(&mut self.stream, "HTTP/1.1", &response)
+            .and_then(|_| self.stream.flush())
+            .is_err()
+        {
+            return;
+        }
+        let _ = self.stream.tcp().shutdown(Shutdown::Write);
+        let deadline = Instant::now() + DRAIN_TIME;
+        let mut drained = 0;
+        let mut buf = [0u8; 8192];
+        while drained < DRAIN_LIMIT {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || self.stream.tcp().set_read_timeout(Some(remaining)).is_err() {
+                break;
+            }
+            match self.reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => drained += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            }
+        }
+    }
+
+    // 返回是否保持连接
+    fn serve(&mut self, request: HttpRequest, bytes_read: u64) -> bool {
+        let server = &self.server;
+        let start = Instant::now();
+        server.requests.fetch_add(1, atomic::Ordering::Relaxed);
+        let queue_wait = mem::take(&mut self.queue_wait);
+        server
+            .queue_wait_micros
+            .fetch_add(queue_wait.as_micros() as u64, atomic::Ordering::Relaxed);
+        let mut ctx = server.dispatch_request(request, queue_wait);
+        ctx.bytes_read = bytes_read;
+        if ctx.response.is_none() {
+            return false;
+        }
+        let remaining = server.max_keep_alive_requests.map(|max| max.saturating_sub(self.served).max(1));
+        self.served += 1;
+        // 写响应时还会执行流式响应的生成函数、static_fallback 与 after_response, 此时已无法改成 500, 只关闭连接
+        let served = panic::catch_unwind(AssertUnwindSafe(|| {
+            let keep_alive = server.handler_response(self.stream.as_mut(), &mut ctx, remaining);
+            if server.access_log {
+                log::access(&ctx, start.elapsed());
+            }
+            for hook in server.after_response_hooks.iter() {
+                hook(&ctx);
+            }
+            keep_alive
+        }));
+        served.unwrap_or_else(|payload| {
+            log::error(&format!(
+                "[{}] {} panicked while responding: {}",
+                ctx.request.id(),
+                ctx.request.path,
+                panic_message(payload.as_ref())
+            ));
+            false
+        })
+    }
+
+    fn close(mut self) {
+        if !self.tunnel {
+            self.stream.close();
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.server.active_connections.fetch_sub(1, atomic::Ordering::Relaxed);
+    }
+}
+
+// 统计实际写到连接上的字节数
+struct CountingStream<'a> {
+    stream: &'a mut dyn Transport,
+    written: u64,
+}
+
+impl CountingStream<'_> {
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.tcp().set_write_timeout(timeout)
+    }
+}
+
+impl Write for CountingStream<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.stream.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+// 统计解析一个请求消耗的字节数, 不包括 BufReader 预读的下一个请求
+struct CountingReader<'a, R> {
+    inner: &'a mut R,
+    read: u64,
+}
+
+impl<R: BufRead> Read for CountingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for CountingReader<'_, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+    fn consume(&mut self, amt: usize) {
+        self.read += amt as u64;
+        self.inner.consume(amt);
+    }
+}
+
+// 响应体的写出限制, 超出时返回错误由调用方中断连接
+struct LimitedWriter<'a, 'b> {
+    stream: &'a mut CountingStream<'b>,
+    written: u64,
+    max_bytes: Option<u64>,
+    deadline: Option<Instant>,
+}
+
+impl Write for LimitedWriter<'_, '_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(max) = self.max_bytes
+            && self.written + buf.len() as u64 > max
+        {
+            return Err(io::Error::other(format!("response body exceeds {} bytes", max)));
+        }
+        if let Some(deadline) = self.deadline {
+            self.stream.set_write_timeout(Some(remaining_time(deadline)?))?;
+        }
+        let n = self.stream.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+// 每次 write 写出一个 chunk, finish 写出结束块
+struct ChunkedWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    fn finish(mut self) -> io::Result<()> {
+        self.inner.write_all(b"0\r\n\r\n")
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // 长度为 0 的块表示结束, 不能写出
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.inner.write_all(format!("{:x}\r\n", buf.len()).as_bytes())?;
+        self.inner.write_all(buf)?;
+        self.inner.write_all(b"\r\n")?;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+    // 一次 write!/writeln! 作为一个 chunk, 而不是按格式化片段拆成多个
+    fn write_fmt(&mut self, args: fmt::Arguments<'_>) -> io::Result<()> {
+        self.write_all(args.to_string().as_bytes())
+    }
+}
+
+// set_write_timeout 不接受 0, 到期直接视为超时
+fn remaining_time(deadline: Instant) -> io::Result<Duration> {
+    deadline
+        .checked_duration_since(Instant::now())
+        .filter(|remaining| !remaining.is_zero())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::TimedOut, "response write time exceeded"))
+}
+
+fn is_supported_version(version: &str) -> bool {
+    version == "HTTP/1.0" || version == "HTTP/1.1"
+}
+
+// 状态行使用与请求相同的协议版本
+fn response_version(request: &HttpRequest) -> &'static str {
+    if request.version == "HTTP/1.0" {
+        "HTTP/1.0"
+    } else {
+        "HTTP/1.1"
+    }
+}
+
+// host 支持 "*" 或 "*." 前缀通配, port 支持 "*"
+fn is_connect_target_match(pattern: &str, target: &str) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+    let (Some((pattern_host, pattern_port)), Some((host, port))) =
+        (pattern.rsplit_once(':'), target.rsplit_once(':'))
+    else {
+        return false;
+    };
+    let port_ok = pattern_port == "*" || pattern_port == port;
+    let host = host.to_ascii_lowercase();
+    let pattern_host = pattern_host.to_ascii_lowercase();
+    let host_ok = if pattern_host == "*" {
+        true
+    } else if let Some(domain) = pattern_host.strip_prefix("*.") {
+        host.ends_with(&format!(".{}", domain))
+    } else {
+        pattern_host == host
+    };
+    port_ok && host_ok
+}
+
+// Host 头去掉端口、末尾的点并转小写, 格式不合法时返回 None
+fn host_name(value: &str) -> Option<String> {
+    let value = value.trim();
+    let (host, port) = match value.strip_prefix('[') {
+        Some(rest) => {
+            let (ip, port) = rest.split_once(']')?;
+            if ip.is_empty() || !ip.chars().all(|c| c.is_ascii_hexdigit() || c == ':' || c == '.') {
+                return None;
+            }
+            (&value[..ip.len() + 2], port)
+        }
+        None => {
+            let (host, port) = value.split_at(value.find(':').unwrap_or(value.len()));
+            if host.is_empty() || !host.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.') {
+                return None;
+            }
+            (host, port)
+        }
+    };
+    if !port.is_empty()
+        && !port
+            .strip_prefix(':')
+            .is_some_and(|p| !p.is_empty() && p.bytes().all(|b| b.is_ascii_digit()))
+    {
+        return None;
+    }
+    Some(host.trim_end_matches('.').to_ascii_lowercase())
+}
+
+// "*" 匹配任意 host, "*.example.com" 匹配其子域名
+fn is_host_match(pattern: &str, host: &str) -> bool {
+    let pattern = pattern.to_ascii_lowercase();
+    if pattern == "*" {
+        return true;
+    }
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host.ends_with(&format!(".{}", domain)),
+        None => pattern == host,
+    }
+}
+
+// 占用一个 CONNECT 隧道名额, drop 时归还
+struct TunnelPermit(Arc<AtomicUsize>);
+
+impl TunnelPermit {
+    fn acquire(active: &Arc<AtomicUsize>, limit: usize) -> Option<TunnelPermit> {
+        active
+            .fetch_update(atomic::Ordering::SeqCst, atomic::Ordering::SeqCst, |n| (n < limit).then_some(n + 1))
+            .ok()?;
+        Some(TunnelPermit(Arc::clone(active)))
+    }
+}
+
+impl Drop for TunnelPermit {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, atomic::Ordering::SeqCst);
+    }
+}
+
+// 双向转发, 任意一端关闭后关闭两端
+fn tunnel(client: TcpStream, upstream: TcpStream) {
+    let (Ok(mut client_reader), Ok(mut upstream_writer)) = (client.try_clone(), upstream.try_clone()) else {
+        return;
+    };
+    let upload = thread::spawn(move || {
+        let _ = io::copy(&mut client_reader, &mut upstream_writer);
+        let _ = upstream_writer.shutdown(Shutdown::Write);
+    });
+    let (mut upstream_reader, mut client_writer) = (upstream, client);
+    let _ = io::copy(&mut upstream_reader, &mut client_writer);
+    let _ = client_writer.shutdown(Shutdown::Both);
+    let _ = upload.join();
+}
+
+/// 当前时间(UTC+8), 日志用
+pub fn format_now()->String{
+    format_datetime(SystemTime::now(), offset8())
+}
+fn offset8() -> Option<Duration> {
+    Some(Duration::from_secs(8 * 60 * 60))
+}
+
+
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub remote_addr: String,
+    pub method: HttpMethod,
+    /// 不含 query, 未做百分号解码
+    pub path: String,
+    pub query_string: String,
+    pub version: String,
+    pub headers: HashMap<String, String>,
+    /// 按 Content-Length 读取的原始字节, 超过 spool_request_body 阈值落盘时为 None
+    pub body: Option<Vec<u8>>,
+    spooled: Option<Arc<SpooledFile>>,
+    id: String,
+    // 首次调用 query() 时才解析
+    params: OnceCell<Vec<(String, String)>>,
+    // 首次调用 cookie() 时才解析
+    cookies: OnceCell<Vec<(String, String)>>,
+    // 路由模式中 `:name` 段捕获的值, 匹配到 handler 后填充
+    path_params: Vec<(String, String)>,
+    secure: bool,
+}
+
+impl HttpRequest {
+    /// 从 reader 读取并解析一个请求, 请求体读入内存, 各方法按默认的 BodyPolicy 处理
+    ///
+    /// 格式错误时返回 Error::Parse, 请求头过大时返回 Error::HeaderTooLarge, 请求体超过 10 MiB 时返回 Error::BodyTooLarge, 连接断开或读取失败时返回 Error::Io, 读超时返回 Error::Timeout
+    pub fn parse(reader: &mut impl BufRead, remote_addr: String) -> Result<HttpRequest, Error> {
+        parse_http_request(reader, remote_addr, None, Some(DEFAULT_MAX_REQUEST_BODY), &[])
+    }
+    /// 请求 id, 取自合法的 X-Request-Id 请求头, 否则在进程内递增生成
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+    /// 名称不区分大小写
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+    /// 是否通过 TLS 连接收到; 在反向代理之后时看不到客户端与代理之间的连接
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+    pub fn content_type(&self) -> Option<MediaType> {
+        MediaType::parse(self.header("Content-Type")?)
+    }
+    // 内部转发时替换 path 与 query, 其余请求数据保持不变
+    fn set_target(&mut self, target: &str) {
+        let (path, query_string) = target.split_once('?').unwrap_or((target, ""));
+        self.path = path.to_string();
+        self.query_string = query_string.to_string();
+        self.params = OnceCell::new();
+        self.path_params.clear();
+    }
+    pub fn has_body(&self) -> bool {
+        self.header("Transfer-Encoding").is_some()
+            || self
+                .header("Content-Length")
+                .and_then(|len| len.trim().parse::<u64>().ok())
+                .is_some_and(|len| len > 0)
+    }
+    /// 请求体字节数, 包括落盘的请求体
+    pub fn body_len(&self) -> u64 {
+        match &self.spooled {
+            Some(file) => file.len,
+            None => self.body.as_ref().map_or(0, |body| body.len() as u64),
+        }
+    }
+    /// 从头读取请求体, 无论在内存中还是已落盘
+    pub fn body_reader(&self) -> io::Result<Box<dyn Read + '_>> {
+        match &self.spooled {
+            Some(file) => Ok(Box::new(File::open(&file.path)?)),
+            None => Ok(Box::new(self.body.as_deref().unwrap_or_default())),
+        }
+    }
+    /// 原始请求体, 适合图片、protobuf 等二进制数据; 在内存中时直接借用, 已落盘时读入内存, 没有请求体时为空
+    pub fn body_bytes(&self) -> io::Result<Cow<'_, [u8]>> {
+        match &self.spooled {
+            Some(file) => Ok(Cow::Owned(std::fs::read(&file.path)?)),
+            None => Ok(Cow::Borrowed(self.body.as_deref().unwrap_or_default())),
+        }
+    }
+    /// 请求体按 Content-Type 的 charset 解码, 没有 charset 时按 UTF-8; 没有请求体时为空字符串
+    ///
+    /// 支持 UTF-8、US-ASCII 与 ISO-8859-1(Latin-1), 其他 charset 返回 Error::UnsupportedCharset,
+    /// 内容与 charset 不符时返回 Error::Parse
+    pub fn text(&self) -> Result<Cow<'_, str>, Error> {
+        let media_type = self.content_type();
+        let charset = media_type.as_ref().and_then(|m| m.charset()).unwrap_or("utf-8");
+        decode_text(self.body_bytes()?, charset)
+    }
+    /// 解码后的 query 参数, 保持原顺序
+    pub fn query_params(&self) -> &Vec<(String, String)> {
+        self.params.get_or_init(|| url::parse_query(&self.query_string))
+    }
+    /// 重复的参数取第一个
+    pub fn query(&self, name: &str) -> Option<&str> {
+        self.query_params()
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+    pub fn query_all(&self, name: &str) -> Vec<&str> {
+        self.query_params()
+            .iter()
+            .filter(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+    /// Cookie 请求头中的 cookie, 值已做百分号解码, 保持原顺序; 没有 Cookie 请求头时为空
+    pub fn cookies(&self) -> &Vec<(String, String)> {
+        self.cookies
+            .get_or_init(|| self.header("Cookie").map(cookie::parse).unwrap_or_default())
+    }
+    /// 名称区分大小写; 同名的 cookie 取第一个, 即浏览器认为最具体的那个
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.cookies()
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+    /// 路由模式中 `:name` 段捕获的值, 已做百分号解码
+    pub fn path_param(&self, name: &str) -> Option<&str> {
+        self.path_params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// 响应, body、stream、view、file 同时只应设置一个
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Option<HashMap<String, String>>,
+    pub body: Option<Vec<u8>>,
+    stream: Option<StreamBody>,
+    view: Option<String>,
+    file: Option<String>,
+    // 自定义的原因短语与设置时的状态码, 状态码之后被改掉(如 304、206、404)时不再使用
+    reason: Option<(u16, String)>,
+}
+
+type StreamProducer = Box<dyn FnOnce(&mut dyn Write) -> io::Result<()> + Send>;
+
+// clone 出的响应共享同一个 producer, 只有先写出的那个会执行它
+#[derive(Clone)]
+struct StreamBody(Arc<Mutex<Option<StreamProducer>>>);
+
+impl StreamBody {
+    fn new<F>(producer: F) -> StreamBody
+    where
+        F: FnOnce(&mut dyn Write) -> io::Result<()> + Send + 'static,
+    {
+        StreamBody(Arc::new(Mutex::new(Some(Box::new(producer)))))
+    }
+    fn take(&self) -> Option<StreamProducer> {
+        self.0.lock().unwrap().take()
+    }
+}
+
+impl fmt::Debug for StreamBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("StreamBody")
+    }
+}
+impl HttpResponse {
+    /// 发送文件内容, 文件不存在时为 404
+    pub fn file(path: String) -> HttpResponse {
+        HttpResponse {
+            status_code: 200,
+            headers: Some(HashMap::from([(
+                "Content-Type".to_string(),
+                "text/html".to_string(),
+            )])),
+            body: None,
+            stream: None,
+            view: None,
+            file: Some(path),
+            reason: None,
+        }
+    }
+    /// 发送 view_root 下的模板文件
+    pub fn view(view_name: String) -> HttpResponse {
+        HttpResponse {
+            status_code: 200,
+            headers: Some(HashMap::from([(
+                "Content-Type".to_string(),
+                "text/html".to_string(),
+            )])),
+            body: None,
+            stream: None,
+            view: Some(view_name),
+            file: None,
+            reason: None,
+        }
+    }
+    pub fn json(json: String) -> HttpResponse {
+        HttpResponse {
+            status_code: 200,
+            headers: Some(HashMap::from([(
+                "Content-Type".to_string(),
+                "application/json".to_string(),
+            )])),
+            body: Some(json.into_bytes()),
+            stream: None,
+            view: None,
+            file: None,
+            reason: None,
+        }
+    }
+    /// 内存中的二进制内容, 如图片、protobuf
+    pub fn bytes(content_type: String, data: Vec<u8>) -> HttpResponse {
+        HttpResponse {
+            status_code: 200,
+            headers: Some(HashMap::from([("Content-Type".to_string(), content_type)])),
+            body: Some(data),
+            stream: None,
+            view: None,
+            file: None,
+            reason: None,
+        }
+    }
+    /// 边生成边发送的响应体, producer 在写出响应时于工作线程中执行
+    ///
+    /// HTTP/1.1 下每次 write 作为一个 chunk 发送(Transfer-Encoding: chunked), 小块较多时可以自行包一层 BufWriter;
+    /// HTTP/1.0 直接写出, 写完后关闭连接
+    pub fn stream<F>(content_type: String, producer: F) -> HttpResponse
+    where
+        F: FnOnce(&mut dyn Write) -> io::Result<()> + Send + 'static,
+    {
+        let mut response = HttpResponse::new(200).add_header("Content-Type".into(), content_type);
+        response.stream = Some(StreamBody::new(producer));
+        response
+    }
+    /// 逐行生成并发送 text/csv, 适合大数据量导出; 字段含 , " 或换行时自动加引号
+    ///
+    /// 如 HttpResponse::csv(users.into_iter().map(|u| [u.id.to_string(), u.name]))
+    pub fn csv<I>(rows: I) -> HttpResponse
+    where
+        I: IntoIterator + Send + 'static,
+        I::IntoIter: Send,
+        I::Item: IntoIterator,
+        <I::Item as IntoIterator>::Item: AsRef<str>,
+    {
+        HttpResponse::stream("text/csv; charset=utf-8".into(), move |out| {
+            write_rows(out, rows, |writer, row| {
+                for (i, field) in row.into_iter().enumerate() {
+                    if i > 0 {
+                        writer.write_all(b",")?;
+                    }
+                    writer.write_all(csv_field(field.as_ref()).as_bytes())?;
+                }
+                writer.write_all(b"\r\n")
+            })
+        })
+    }
+    /// 逐行发送 application/x-ndjson, 每个元素是一个已经序列化好的 JSON 对象
+    pub fn ndjson<I>(records: I) -> HttpResponse
+    where
+        I: IntoIterator + Send + 'static,
+        I::IntoIter: Send,
+        I::Item: AsRef<str>,
+    {
+        HttpResponse::stream("application/x-ndjson".into(), move |out| {
+            write_rows(out, records, |writer, record| {
+                writer.write_all(record.as_ref().as_bytes())?;
+                writer.write_all(b"\n")
+            })
+        })
+    }
+    pub fn new(status_code: u16) -> HttpResponse {
+        HttpResponse {
+            status_code,
+            headers: None,
+            body: None,
+            stream: None,
+            view: None,
+            file: None,
+            reason: None,
+        }
+    }
+    pub fn status_code(mut self, status_code: u16) -> Self {
+        self.status_code = status_code;
+        self
+    }
+    /// 替换状态行中的原因短语, 如 .reason("Teapot"); 只对当前状态码生效, CR、LF 等控制字符会被去掉
+    pub fn reason(mut self, reason: &str) -> Self {
+        self.reason = Some((self.status_code, status::sanitize_reason(reason)));
+        self
+    }
+    pub fn headers(mut self, headers: HashMap<String, String>) -> Self {
+        self.headers = Some(headers);
+        self
+    }
+    pub fn add_header(mut self, key: String, value: String) -> Self {
+        if self.headers.is_none() {
+            self.headers = Some(HashMap::new());
+        }
+        self.headers.as_mut().unwrap().insert(key, value);
+        self
+    }
+    /// 文本或二进制内容, 如 .body("ok") 或 .body(vec![0u8; 4])
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = Some(body.into());
+        self
+    }
+    /// 追加到 Vary, 不区分大小写去重, 已经是 * 时不再追加; 按请求头选择响应内容时需要设置, 否则共享缓存可能返回错误的版本
+    pub fn add_vary(mut self, name: &str) -> Self {
+        self.merge_vary(name);
+        self
+    }
+    fn merge_vary(&mut self, name: &str) {
+        let mut names = self
+            .header("Vary")
+            .map(|vary| {
+                vary.split(',')
+                    .map(|n| n.trim().to_string())
+                    .filter(|n| !n.is_empty())
+                    .collect::<Vec<String>>()
+            })
+            .unwrap_or_default();
+        if names.iter().any(|n| n == "*" || n.eq_ignore_ascii_case(name)) {
+            return;
+        }
+        if name == "*" {
+            names.clear();
+        }
+        names.push(name.to_string());
+        self.set_header("Vary", names.join(", "));
+    }
+    pub fn is_stream(&self) -> bool {
+        self.stream.is_some()
+    }
+    // 替换同名(不区分大小写)的已有响应头
+    fn set_header(&mut self, name: &str, value: String) {
+        self.remove_header(name);
+        self.headers.get_or_insert_with(HashMap::new).insert(name.to_string(), value);
+    }
+    fn remove_header(&mut self, name: &str) {
+        if let Some(headers) = self.headers.as_mut() {
+            headers.retain(|key, _| !key.eq_ignore_ascii_case(name));
+        }
+    }
+    fn memory_body(&self) -> &[u8] {
+        self.body.as_deref().unwrap_or_default()
+    }
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .as_ref()?
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+// 攒够 FLUSH_ROWS 行或缓冲区满时才写出一个 chunk, 行很多时不至于每行一个 chunk, 生成慢时客户端也能及时收到
+const FLUSH_ROWS: usize = 100;
+
+fn write_rows<I, F>(out: &mut dyn Write, rows: I, mut write_row: F) -> io::Result<()>
+where
+    I: IntoIterator,
+    F: FnMut(&mut dyn Write, I::Item) -> io::Result<()>,
+{
+    let mut writer = io::BufWriter::new(out);
+    for (i, row) in rows.into_iter().enumerate() {
+        write_row(&mut writer, row)?;
+        if (i + 1) % FLUSH_ROWS == 0 {
+            writer.flush()?;
+        }
+    }
+    writer.flush()
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// 解析 HTTP 请求
+// reader 在同一个连接的多个请求之间复用, 否则已缓冲的下一个请求会丢失
+fn parse_http_request(
+    reader: &mut impl BufRead,
+    remote_addr: String,
+    spool: Option<&SpoolConfig>,
+    max_body: Option<u64>,
+    body_policies: &[(HttpMethod, BodyPolicy)],
+) -> Result<HttpRequest, Error> {
+    // 每行读到同一个 scratch 中, 边读边解析, 超过限制时立即停止读取
+    let mut scratch = Vec::new();
+    let mut remaining = MAX_HEAD_SIZE;
+
+    // 客户端在发送请求前关闭了连接
+    if !read_head_line(reader, &mut scratch, &mut remaining)? || scratch.is_empty() {
+        return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()));
+    }
+    // 解析请求行
+    let line = head_line(&scratch)?;
+    let request_line = line.split_whitespace().collect::<Vec<&str>>();
+    if request_line.len() != 3 {
+        return Err(Error::Parse(format!("invalid request line: {}", line)));
+    }
+    let method = HttpMethod::name_of(request_line[0].to_uppercase())
+        .ok_or_else(|| Error::Parse(format!("unknown method: {}", request_line[0])))?;
+    // absolute-form: GET http://example.com/a HTTP/1.1, 此时以 URI 中的 host 为准
+    let (authority, target) = match split_absolute_form(request_line[1]) {
+        Some((authority, target)) => (Some(authority.to_string()), target),
+        None => (None, request_line[1].to_string()),
+    };
+    let (path, query_string) = match target.split_once('?') {
+        Some((path, query)) => (path.to_string(), query.to_string()),
+        None => (target.to_string(), String::new()),
+    };
+    let version = request_line[2].to_string();
+
+    // 解析请求头
+    let mut headers: HashMap<String, String> = HashMap::new();
+    let mut count = 0;
+    while read_head_line(reader, &mut scratch, &mut remaining)? && !scratch.is_empty() {
+        count += 1;
+        if count > MAX_HEADERS {
+            return Err(Error::HeaderTooLarge);
+        }
+        if let Some((key, value)) = head_line(&scratch)?.split_once(": ") {
+            // 重复的 Content-Length 值不同时无法确定请求体在哪里结束, 见 RFC 9112 6.3
+            if key.eq_ignore_ascii_case("Content-Length")
+                && let Some((_, previous)) = headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(key))
+                && content_length_value(previous) != content_length_value(value)
+            {
+                return Err(Error::Parse(format!("conflicting Content-Length: {}, {}", previous, value)));
+            }
+            headers.insert(key.to_string(), value.to_string());
+        }
+    }
+
+    if let Some(authority) = authority {
+        headers.retain(|key, _| !key.eq_ignore_ascii_case("Host"));
+        headers.insert("Host".to_string(), authority);
+    }
+
+    // 解析请求体, 是否读取由方法对应的 BodyPolicy 决定
+    let find_header = |name: &str| {
+        headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim())
+    };
+    let (transfer_encoding, content_length) = (find_header("Transfer-Encoding"), find_header("Content-Length"));
+    // 两者同时出现是请求走私的常见手法, 前后两个服务器可能各按其中一个确定请求体的长度
+    if transfer_encoding.is_some() && content_length.is_some() {
+        return Err(Error::Parse("both Transfer-Encoding and Content-Length".into()));
+    }
+    let mut buffer = BodyBuffer::new(spool);
+    match body_policy(body_policies, &method) {
+        BodyPolicy::Read => read_body(reader, transfer_encoding, content_length, max_body, &mut buffer)?,
+        BodyPolicy::Ignore => read_body(reader, transfer_encoding, content_length, max_body, &mut io::sink())?,
+        // 由调用方回复 400
+        BodyPolicy::Reject => {}
+    }
+    let (body, spooled) = match buffer.finish()? {
+        Body::Memory(body) if body.is_empty() => (None, None),
+        Body::Memory(body) => (Some(body), None),
+        Body::Spooled(file) => (None, Some(file)),
+    };
+
+    Ok(HttpRequest {
+        remote_addr,
+        method,
+        path,
+        query_string,
+        version,
+        id: request_id(&headers),
+        headers,
+        body,
+        spooled,
+        params: OnceCell::new(),
+        cookies: OnceCell::new(),
+        path_params: Vec::new(),
+        secure: false,
+    })
+}
+
+// 请求行与请求头合计的字节数、单行字节数与请求头个数上限, 超过时回复 431
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+const MAX_HEAD_LINE: usize = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+
+// 读取一行到 scratch, 不含结尾的 CRLF; 连接关闭且没有读到数据时返回 false
+// 直接在 BufRead 的缓冲区里找换行, 一行超过 MAX_HEAD_LINE 或总量超过 remaining 时不再继续读
+fn read_head_line(reader: &mut impl BufRead, scratch: &mut Vec<u8>, remaining: &mut usize) -> Result<bool, Error> {
+    scratch.clear();
+    loop {
+        let available = match reader.fill_buf() {
+            Ok(available) => available,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        if available.is_empty() {
+            if scratch.is_empty() {
+                return Ok(false);
+            }
+            // 一行还没有结束连接就关闭了
+            return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()));
+        }
+        let newline = available.iter().position(|b| *b == b'\n');
+        let len = newline.map_or(available.len(), |i| i + 1);
+        if scratch.len() + len > MAX_HEAD_LINE || len > *remaining {
+            return Err(Error::HeaderTooLarge);
+        }
+        scratch.extend_from_slice(&available[..len]);
+        reader.consume(len);
+        *remaining -= len;
+        if newline.is_some() {
+            scratch.pop();
+            if scratch.last() == Some(&b'\r') {
+                scratch.pop();
+            }
+            return Ok(true);
+        }
+    }
+}
+
+fn head_line(scratch: &[u8]) -> Result<&str, Error> {
+    std::str::from_utf8(scratch).map_err(|_| Error::Parse("request head is not valid UTF-8".into()))
+}
+
+// 请求体的默认上限, 见 HttpServer::max_request_body
+const DEFAULT_MAX_REQUEST_BODY: u64 = 10 * 1024 * 1024;
+const DEFAULT_MAX_TUNNELS: usize = 256;
+const DEFAULT_TIMEOUT_THREADS: usize = 64;
+// 拒绝未读完的请求后, 关闭连接前最多丢弃的输入与等待时间
+const DRAIN_LIMIT: usize = 64 * 1024;
+const DRAIN_TIME: Duration = Duration::from_secs(1);
+
+// 只接受不超过 64 个可见 ASCII 字符的 X-Request-Id, 避免日志注入
+fn request_id(headers: &HashMap<String, String>) -> String {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+    let client_id = headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("X-Request-Id"))
+        .map(|(_, value)| value.trim())
+        .filter(|id| !id.is_empty() && id.len() <= 64 && id.bytes().all(|b| b.is_ascii_graphic()));
+    match client_id {
+        Some(id) => id.to_string(),
+        None => NEXT_ID.fetch_add(1, atomic::Ordering::Relaxed).to_string(),
+    }
+}
+
+// 返回 (authority, path[?query]), path 为空时补 /
+fn split_absolute_form(target: &str) -> Option<(&str, String)> {
+    let rest = target
+        .strip_prefix("http://")
+        .or_else(|| target.strip_prefix("https://"))?;
+    let (authority, target) = rest.split_at(rest.find(['/', '?']).unwrap_or(rest.len()));
+    if target.starts_with('/') {
+        Some((authority, target.to_string()))
+    } else {
+        Some((authority, format!("/{}", target)))
+    }
+}
+
+fn error_handler_for(handlers: &[(u16, HttpHandler)], status_code: u16) -> Option<&HttpHandler> {
+    handlers.iter().find(|(status, _)| *status == status_code).map(|(_, handler)| handler)
+}
+
+fn body_policy(policies: &[(HttpMethod, BodyPolicy)], method: &HttpMethod) -> BodyPolicy {
+    policies
+        .iter()
+        .find(|(m, _)| m == method)
+        .map_or_else(|| BodyPolicy::default_for(method), |(_, policy)| *policy)
+}
+
+fn decode_text<'a>(bytes: Cow<'a, [u8]>, charset: &str) -> Result<Cow<'a, str>, Error> {
+    let invalid = || Error::Parse(format!("request body is not valid {}", charset));
+    let utf8 = |bytes: Cow<'a, [u8]>| match bytes {
+        Cow::Borrowed(bytes) => std::str::from_utf8(bytes).map(Cow::Borrowed).map_err(|_| invalid()),
+        Cow::Owned(bytes) => String::from_utf8(bytes).map(Cow::Owned).map_err(|_| invalid()),
+    };
+    match charset.to_ascii_lowercase().as_str() {
+        "utf-8" | "utf8" => utf8(bytes),
+        "us-ascii" | "ascii" if bytes.is_ascii() => utf8(bytes),
+        "us-ascii" | "ascii" => Err(invalid()),
+        // 每个字节就是同值的 Unicode 码点
+        "iso-8859-1" | "iso_8859-1" | "latin1" | "latin-1" | "l1" => {
+            Ok(Cow::Owned(bytes.iter().map(|&b| b as char).collect()))
+        }
+        _ => Err(Error::UnsupportedCharset(charset.to_string())),
+    }
+}
+
+// 按 Transfer-Encoding 或 Content-Length 读取原始字节, 超过 max_body 时返回 Error::BodyTooLarge
+fn read_body(
+    reader: &mut impl BufRead,
+    transfer_encoding: Option<&str>,
+    content_length: Option<&str>,
+    max_body: Option<u64>,
+    body: &mut impl Write,
+) -> Result<(), Error> {
+    if let Some(transfer_encoding) = transfer_encoding {
+        // chunked 必须是最后一个编码, 否则无法确定请求体在哪里结束
+        let is_chunked = transfer_encoding
+            .rsplit(',')
+            .next()
+            .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"));
+        if !is_chunked {
+            return Err(Error::Parse(format!("unsupported Transfer-Encoding: {}", transfer_encoding)));
+        }
+        return read_chunked_body(reader, max_body, body);
+    }
+    let content_length = match content_length {
+        Some(value) => content_length_value(value).ok_or_else(|| Error::Parse(format!("invalid Content-Length: {}", value)))?,
+        None => 0,
+    };
+    // 声明的长度已经超出时不再读取
+    if max_body.is_some_and(|max| content_length > max) {
+        return Err(Error::BodyTooLarge);
+    }
+    read_exact_body(reader, content_length, body)
+}
+
+// 只接受十进制数字, 不接受 +5 这样 u64::parse 能解析的写法; 5, 5 这样值相同的列表按一个值处理(RFC 9112 6.3)
+fn content_length_value(value: &str) -> Option<u64> {
+    let mut values = value.split(',').map(str::trim);
+    let first = values.next()?;
+    if first.is_empty() || !first.bytes().all(|b| b.is_ascii_digit()) || values.any(|v| v != first) {
+        return None;
+    }
+    first.parse().ok()
+}
+
+// 逐步读取, 不按客户端声明的长度预先分配
+fn read_exact_body(reader: &mut impl BufRead, len: u64, body: &mut impl Write) -> Result<(), Error> {
+    let copied = io::copy(&mut reader.by_ref().take(len), body)?;
+    if copied < len {
+        return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()));
+    }
+    Ok(())
+}
+
+// 每块: 十六进制长度[;扩展]\r\n 数据\r\n, 以长度为 0 的块结束, 之后是可选的 trailer 与空行
+// 长度行与 trailer 都按请求头的方式限制长度, 解码后的总长度不能超过 max_body
+fn read_chunked_body(reader: &mut impl BufRead, max_body: Option<u64>, body: &mut impl Write) -> Result<(), Error> {
+    let mut scratch = Vec::new();
+    let mut total = 0u64;
+    loop {
+        // 长度行不计入 trailer 的总量
+        let mut remaining = MAX_HEAD_LINE;
+        let read = read_head_line(reader, &mut scratch, &mut remaining).map_err(|e| match e {
+            Error::HeaderTooLarge => Error::Parse("chunk size line too long".into()),
+            e => e,
+        })?;
+        if !read {
+            return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()));
+        }
+        let size_line = head_line(&scratch)?;
+        let size = size_line.split(';').next().unwrap_or("").trim();
+        let size = u64::from_str_radix(size, 16).map_err(|_| Error::Parse(format!("invalid chunk size: {}", size)))?;
+        if size == 0 {
+            break;
+        }
+        total = total.saturating_add(size);
+        if max_body.is_some_and(|max| total > max) {
+            return Err(Error::BodyTooLarge);
+        }
+        read_exact_body(reader, size, body)?;
+        let mut crlf = [0u8; 2];
+        reader.read_exact(&mut crlf)?;
+        if &crlf != b"\r\n" {
+            return Err(Error::Parse("missing CRLF after chunk".into()));
+        }
+    }
+    // trailer 不合并到请求头中, 个数与总量的限制与请求头相同
+    let mut remaining = MAX_HEAD_SIZE;
+    let mut count = 0;
+    while read_head_line(reader, &mut scratch, &mut remaining)? && !scratch.is_empty() {
+        count += 1;
+        if count > MAX_HEADERS {
+            return Err(Error::HeaderTooLarge);
+        }
+    }
+    Ok(())
+}
+
+fn format_datetime(system_time: SystemTime, offset: Option<Duration>) -> String {
+    let duration = system_time.duration_since(UNIX_EPOCH).unwrap();
+    let mut seconds = duration.as_secs();
+    if let Some(offset) = offset {
+        seconds += offset.as_secs();
+    }
+    let (year, month, day, hour, minute, second) = civil_time(seconds);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        hour,
+        minute,
+        second
+    )
+}
+
+const DAYS_IN_MONTH: [u64; 12] = [31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+
+// 1970-01-01 00:00:00 之后的秒数转为 (年, 月, 日, 时, 分, 秒), 月、日从 1 开始
+fn civil_time(mut seconds: u64) -> (i32, u64, u64, u64, u64, u64) {
+    let mut year = 1970;
+    while {
+        let is_leap = is_leap_year(year);
+        let days_in_year = if is_leap { 366 } else { 365 };
+        seconds >= days_in_year * 86400
+    } {
+        let is_leap = is_leap_year(year);
+        let days_in_year = if is_leap { 366 } else { 365 };
+        seconds -= days_in_year * 86400;
+        year += 1;
+    }
+
+    let is_leap = is_leap_year(year);
+    let mut month = 0;
+    while {
+        let days = DAYS_IN_MONTH[month] + if month == 1 && is_leap { 1 } else { 0 };
+        seconds >= days * 86400
+    } {
+        let days = DAYS_IN_MONTH[month] + if month == 1 && is_leap { 1 } else { 0 };
+        seconds -= days * 86400;
+        month += 1;
+    }
+
+    let day = (seconds / 86400) + 1;
+    seconds %= 86400;
+    let hour = seconds / 3600;
+    seconds %= 3600;
+    let minute = seconds / 60;
+    let second = seconds % 60;
+    (year, month as u64 + 1, day, hour, minute, second)
+}
+
+// civil_time 的逆运算, 年份早于 1970 或月、日超出范围时为 None
+fn unix_seconds(year: i32, month: u64, day: u64, hour: u64, minute: u64, second: u64) -> Option<u64> {
+    if year < 1970 || !(1..=12).contains(&month) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    let is_leap = is_leap_year(year);
+    let month_days = DAYS_IN_MONTH[month as usize - 1] + if month == 2 && is_leap { 1 } else { 0 };
+    if day == 0 || day > month_days {
+        return None;
+    }
+    let mut days = (1970..year).map(|y| if is_leap_year(y) { 366 } else { 365 }).sum::<u64>();
+    days += (0..month as usize - 1)
+        .map(|m| DAYS_IN_MONTH[m] + if m == 1 && is_leap { 1 } else { 0 })
+        .sum::<u64>();
+    days += day - 1;
+    Some(days * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+// 判断是否为闰年
+fn is_leap_year(year: i32) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || (year % 400 == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &[u8], max_body: Option<u64>) -> Result<HttpRequest, Error> {
+        parse_http_request(&mut &raw[..], "127.0.0.1:50000".into(), None, max_body, &[])
+    }
+
+    #[test]
+    fn conflicting_content_length_is_rejected() {
+        let raw = b"POST / HTTP/1.1\r\nContent-Length: 3\r\nContent-Length: 4\r\n\r\nabcd";
+        assert!(matches!(parse(raw, None), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn repeated_equal_content_length_is_accepted() {
+        let raw = b"POST / HTTP/1.1\r\nContent-Length: 3\r\nContent-Length: 3\r\n\r\nabc";
+        assert_eq!(parse(raw, None).unwrap().body.as_deref(), Some(&b"abc"[..]));
+        let raw = b"POST / HTTP/1.1\r\nContent-Length: 3, 3\r\n\r\nabc";
+        assert_eq!(parse(raw, None).unwrap().body.as_deref(), Some(&b"abc"[..]));
+    }
+
+    #[test]
+    fn content_length_must_be_digits() {
+        let raw = b"POST / HTTP/1.1\r\nContent-Length: +3\r\n\r\nabc";
+        assert!(matches!(parse(raw, None), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn content_length_with_transfer_encoding_is_rejected() {
+        let raw = b"POST / HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n";
+        assert!(matches!(parse(raw, None), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn declared_body_over_limit_is_rejected_before_reading() {
+        let raw = b"POST / HTTP/1.1\r\nContent-Length: 4\r\n\r\nabcd";
+        assert!(matches!(parse(raw, Some(3)), Err(Error::BodyTooLarge)));
+        assert!(parse(raw, Some(4)).is_ok());
+    }
+    fn chunked(body: &str) -> Vec<u8> {
+        format!("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n{}", body).into_bytes()
+    }
+
+    #[test]
+    fn chunked_body_is_decoded_and_trailers_skipped() {
+        let raw = chunked("3;ext=1\r\nabc\r\n2\r\nde\r\n0\r\nX-Trailer: 1\r\n\r\n");
+        assert_eq!(parse(&raw, None).unwrap().body.as_deref(), Some(&b"abcde"[..]));
+    }
+
+    #[test]
+    fn chunked_body_over_limit_is_rejected() {
+        let raw = chunked("3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n");
+        assert!(matches!(parse(&raw, Some(4)), Err(Error::BodyTooLarge)));
+        assert!(parse(&raw, Some(5)).is_ok());
+    }
+
+    #[test]
+    fn chunk_size_line_is_bounded() {
+        let raw = chunked(&format!("3;{}\r\nabc\r\n0\r\n\r\n", "x".repeat(MAX_HEAD_LINE)));
+        assert!(matches!(parse(&raw, None), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn trailers_are_bounded() {
+        let trailers = (0..=MAX_HEADERS).map(|i| format!("X-T{}: v\r\n", i)).collect::<String>();
+        let raw = chunked(&format!("0\r\n{}\r\n", trailers));
+        assert!(matches!(parse(&raw, None), Err(Error::HeaderTooLarge)));
+        let raw = chunked(&format!("0\r\nX-T: {}\r\n\r\n", "v".repeat(MAX_HEAD_LINE)));
+        assert!(matches!(parse(&raw, None), Err(Error::HeaderTooLarge)));
+    }
+
+    // 依次记录经过的中间件; scope 为空时是全局中间件
+    fn recording(events: &Arc<Mutex<Vec<String>>>, name: &str, order: usize, scope: Option<&str>) -> Middleware {
+        let (events, label) = (Arc::clone(events), name.to_string());
+        let middleware = Middleware::new(move |chain, ctx| {
+            events.lock().unwrap().push(format!("{}-before", label));
+            chain.next(ctx);
+            events.lock().unwrap().push(format!("{}-after", label));
+        })
+        .name(name.into())
+        .order(order);
+        match scope {
+            Some(path) => middleware.path(path.into()),
+            None => middleware,
+        }
+    }
+
+    fn get(path: &str) -> HttpRequest {
+        let raw = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+        HttpRequest::parse(&mut raw.as_bytes(), "127.0.0.1:50000".into()).unwrap()
+    }
+
+    fn interleaved_server(events: &Arc<Mutex<Vec<String>>>) -> HttpServer {
+        let mut server = HttpServer::new("127.0.0.1:0".into());
+        let handler_events = Arc::clone(events);
+        server.add_handler(HttpMethod::GET, "/api/users".into(), move |ctx| {
+            handler_events.lock().unwrap().push("handler".into());
+            ctx.set_response(HttpResponse::new(200));
+        });
+        // 注册顺序与 order 故意交错: 全局与 /api/** 的中间件各有大有小, 还有 order 相同的
+        server.add_middleware(recording(events, "metrics", 20, None));
+        server.add_middleware(recording(events, "api-auth", 10, Some("/api/**")));
+        server.add_middleware(recording(events, "log", 0, None));
+        server.add_middleware(recording(events, "api-audit", 10, Some("/api/**")));
+        server.add_middleware(recording(events, "admin-only", 5, Some("/admin/**")));
+        server.add_middleware(recording(events, "request-id", 10, None));
+        server
+    }
+
+    #[test]
+    fn interleaved_global_and_route_middleware_run_by_order() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let server = interleaved_server(&events);
+        assert_eq!(server.call(get("/api/users")).status_code, 200);
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                "log-before",
+                "api-auth-before",
+                "api-audit-before",
+                "request-id-before",
+                "metrics-before",
+                "handler",
+                "metrics-after",
+                "request-id-after",
+                "api-audit-after",
+                "api-auth-after",
+                "log-after",
+            ]
+        );
+    }
+
+    #[test]
+    fn route_middleware_is_skipped_outside_its_path() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let server = interleaved_server(&events);
+        assert_eq!(server.call(get("/other")).status_code, 404);
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                "log-before",
+                "request-id-before",
+                "metrics-before",
+                "metrics-after",
+                "request-id-after",
+                "log-after",
+            ]
+        );
+    }
+
+    #[test]
+    fn describe_middlewares_matches_runtime_order() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let server = interleaved_server(&events);
+        server.call(get("/api/users"));
+        let described = server
+            .describe_middlewares(&HttpMethod::GET, "/api/users")
+            .into_iter()
+            .map(|line| format!("{}-before", line.split(' ').next().unwrap()))
+            .collect::<Vec<String>>();
+        let ran = events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|e| e.ends_with("-before"))
+            .cloned()
+            .collect::<Vec<String>>();
+        assert_eq!(described, ran);
+    }
+
+    // 改用前缀树之前的查找方式: 逐个比较全部路由, 作为对照
+    fn linear_find<'a>(server: &'a HttpServer, method: &HttpMethod, path: &str) -> Option<&'a RequestMapping> {
+        let mut best: Option<&RequestMapping> = None;
+        for mapping in server
+            .handlers
+            .iter()
+            .filter(|m| is_path_match(&m.path, path) && server.is_method_match(method, m))
+        {
+            let more_specific = best.is_none_or(|b| {
+                compare_specificity(&mapping.path, &b.path)
+                    .then_with(|| b.methods.is_empty().cmp(&mapping.methods.is_empty()))
+                    == Ordering::Greater
+            });
+            if more_specific {
+                best = Some(mapping);
+            }
+        }
+        best
+    }
+
+    // 固定种子的线性同余生成器, 每次运行生成相同的用例
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self, bound: usize) -> usize {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (self.0 >> 33) as usize % bound
+        }
+        fn path(&mut self, pool: &[&str], max_len: usize) -> String {
+            let len = self.next(max_len + 1);
+            (0..len).map(|_| format!("/{}", pool[self.next(pool.len())])).collect::<String>()
+        }
+    }
+
+    const PATTERN_SEGMENTS: [&str; 11] = ["api", "v1", "users", "*.php", "app-*.log", ":id", "*", "**", "static", "a*b", "x"];
+    const PATH_SEGMENTS: [&str; 10] = ["api", "v1", "users", "index.php", "app-1.log", "x", "static", "aXb", "42", "ab"];
+
+    // 每个模式规范化后只注册一次, 交替限定 GET 与任意方法
+    fn random_routes(seed: u64, count: usize) -> HttpServer {
+        let mut rng = Lcg(seed);
+        let mut server = HttpServer::new("127.0.0.1:0".into());
+        let mut seen = std::collections::HashSet::new();
+        while server.handlers.len() < count {
+            let pattern = rng.path(&PATTERN_SEGMENTS, 4);
+            let pattern = if pattern.is_empty() { "/".to_string() } else { pattern };
+            if !seen.insert(normalize(&pattern)) {
+                continue;
+            }
+            let methods: &[HttpMethod] = if server.handlers.len().is_multiple_of(2) { &[HttpMethod::GET] } else { &[] };
+            server.add_handler_for(methods, pattern, |_| {});
+        }
+        server
+    }
+
+    #[test]
+    fn router_picks_the_same_route_as_linear_scan() {
+        for seed in 1..=20 {
+            let server = random_routes(seed, 60);
+            let mut rng = Lcg(seed * 7919);
+            for _ in 0..300 {
+                let path = rng.path(&PATH_SEGMENTS, 5);
+                for method in [HttpMethod::GET, HttpMethod::POST] {
+                    let trie = server.find_handler(&method, &path).map(|m| m.path.as_str());
+                    let linear = linear_find(&server, &method, &path).map(|m| m.path.as_str());
+                    assert_eq!(trie, linear, "seed {} {:?} {}", seed, method, path);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn router_resolves_each_pattern_kind() {
+        let mut server = HttpServer::new("127.0.0.1:0".into());
+        for pattern in ["/files/**", "/files/*.php", "/files/:name", "/files/*", "/files/readme", "/**/*.log", "/a/**/z"] {
+            server.add_handler(HttpMethod::GET, pattern.into(), |_| {});
+        }
+        let cases = [
+            ("/files/readme", Some("/files/readme")),
+            ("/files/index.php", Some("/files/*.php")),
+            ("/files/photo.png", Some("/files/:name")),
+            ("/files/a/b", Some("/files/**")),
+            ("/files", Some("/files/**")),
+            ("/var/log/app.log", Some("/**/*.log")),
+            ("/a/z", Some("/a/**/z")),
+            ("/a/b/c/z", Some("/a/**/z")),
+            ("/b/z", None),
+        ];
+        for (path, expected) in cases {
+            let trie = server.find_handler(&HttpMethod::GET, path).map(|m| m.path.as_str());
+            let linear = linear_find(&server, &HttpMethod::GET, path).map(|m| m.path.as_str());
+            assert_eq!(trie, expected, "{}", path);
+            assert_eq!(linear, expected, "{}", path);
+        }
+    }
+
+    // 计时对比, 默认不运行: cargo test --release --lib route_lookup_benchmark -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn route_lookup_benchmark() {
+        let mut server = HttpServer::new("127.0.0.1:0".into());
+        for i in 0..500 {
+            server.add_handler(HttpMethod::GET, format!("/api/v1/resource{}/:id", i), |_| {});
+        }
+        let paths = (0..1000).map(|i| format!("/api/v1/resource{}/42", i % 500)).collect::<Vec<String>>();
+        let time = |find: &dyn Fn(&str) -> bool| {
+            let start = Instant::now();
+            for path in paths.iter() {
+                assert!(find(path));
+            }
+            start.elapsed() / paths.len() as u32
+        };
+        let trie = time(&|path| server.find_handler(&HttpMethod::GET, path).is_some());
+        let linear = time(&|path| linear_find(&server, &HttpMethod::GET, path).is_some());
+        println!("500 routes, per lookup: trie {:?}, linear scan {:?}", trie, linear);
+        assert!(trie < linear);
+    }
+
+    fn run_in_background(server: HttpServer) -> mpsc::Receiver<Result<(), Error>> {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || sender.send(server.run()));
+        receiver
+    }
+
+    #[test]
+    fn shutdown_before_run_does_not_block_in_accept() {
+        let server = HttpServer::new("127.0.0.1:0".into());
+        server.shutdown_handle().shutdown();
+        let finished = run_in_background(server);
+        assert!(matches!(finished.recv_timeout(Duration::from_secs(5)), Ok(Ok(()))));
+    }
+
+    #[test]
+    fn shutdown_stops_admin_and_reload_threads() {
+        let config = std::env::temp_dir().join(format!("rustbook-httpserver-{}.conf", std::process::id()));
+        std::fs::write(&config, "maintenance = false\n").unwrap();
+        let mut server = HttpServer::new("127.0.0.1:0".into());
+        server.admin_address("127.0.0.1:0".into());
+        server.watch_config(config.to_string_lossy().into_owned());
+        let handle = server.shutdown_handle();
+        let finished = run_in_background(server);
+        thread::sleep(Duration::from_millis(200));
+        handle.shutdown();
+        // run 在 join 管理端口与配置轮询线程之后才返回
+        assert!(matches!(finished.recv_timeout(Duration::from_secs(5)), Ok(Ok(()))));
+        let _ = std::fs::remove_file(config);
+    }
+
+    #[test]
+    fn admin_bind_failure_is_returned_from_run() {
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut server = HttpServer::new("127.0.0.1:0".into());
+        server.admin_address(taken.local_addr().unwrap().to_string());
+        assert!(matches!(server.run(), Err(Error::Bind(_))));
+    }
+
+    // 返回服务器地址, 结束时调用 ShutdownHandle::shutdown
+    fn serve_in_background(server: HttpServer) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || server.serve(listener, None));
+        address
+    }
+
+    // 读到响应头结束, 不读响应体
+    fn read_head(stream: &mut TcpStream) -> String {
+        let mut head = Vec::new();
+        let mut byte = [0u8; 1];
+        while !head.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap() == 1 {
+            head.push(byte[0]);
+        }
+        String::from_utf8_lossy(&head).into_owned()
+    }
+
+    fn read_status_line(stream: &mut TcpStream) -> String {
+        read_head(stream).lines().next().unwrap_or_default().to_string()
+    }
+
+    #[test]
+    fn connect_forwards_bytes_sent_with_the_request_head() {
+        let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = upstream.local_addr().unwrap().to_string();
+        let mut server = HttpServer::new("127.0.0.1:0".into());
+        server.allow_connect("127.0.0.1:*".into());
+        let handle = server.shutdown_handle();
+        let address = serve_in_background(server);
+
+        let mut client = TcpStream::connect(&address).unwrap();
+        let raw = format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n\r\nhello", target, target);
+        client.write_all(raw.as_bytes()).unwrap();
+        let (mut accepted, _) = upstream.accept().unwrap();
+        accepted.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut received = [0u8; 5];
+        accepted.read_exact(&mut received).unwrap();
+        assert_eq!(&received, b"hello");
+        assert_eq!(read_status_line(&mut client), "HTTP/1.1 200 OK");
+        handle.shutdown();
+    }
+
+    #[test]
+    fn connect_tunnels_are_limited() {
+        let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = upstream.local_addr().unwrap().to_string();
+        let mut server = HttpServer::new("127.0.0.1:0".into());
+        server.allow_connect("127.0.0.1:*".into());
+        server.max_tunnels(1);
+        let handle = server.shutdown_handle();
+        let address = serve_in_background(server);
+        let connect = || {
+            let mut client = TcpStream::connect(&address).unwrap();
+            client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let raw = format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n\r\n", target, target);
+            client.write_all(raw.as_bytes()).unwrap();
+            let status = read_status_line(&mut client);
+            (client, status)
+        };
+
+        let (first, status) = connect();
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        let (accepted, _) = upstream.accept().unwrap();
+        assert_eq!(connect().1, "HTTP/1.1 503 Service Unavailable");
+        // 第一个隧道关闭后名额归还
+        drop(first);
+        drop(accepted);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let status = loop {
+            let (_client, status) = connect();
+            if status.contains("200") || Instant::now() > deadline {
+                break status;
+            }
+            thread::sleep(Duration::from_millis(20));
+        };
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        handle.shutdown();
+    }
+
+    // 发送一个带 Connection: close 的请求, 读到连接关闭为止
+    fn exchange(address: &str, raw: &str) -> Vec<u8> {
+        let mut client = TcpStream::connect(address).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        client.write_all(raw.as_bytes()).unwrap();
+        let mut response = Vec::new();
+        let _ = client.read_to_end(&mut response);
+        response
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn no_transform_responses_are_not_compressed() {
+        let mut server = HttpServer::new("127.0.0.1:0".into());
+        server.compress_responses(0);
+        for (path, cache_control) in [("/plain", "max-age=60"), ("/no-transform", "max-age=60, No-Transform")] {
+            server.add_handler(HttpMethod::GET, path.into(), move |ctx| {
+                let response = HttpResponse::bytes("text/plain".into(), "hello ".repeat(100).into_bytes())
+                    .add_header("Cache-Control".into(), cache_control.into())
+                    .add_header("ETag".into(), "\"v1\"".into());
+                ctx.set_response(response)
+            });
+        }
+        let handle = server.shutdown_handle();
+        let address = serve_in_background(server);
+        let get = |path: &str, extra: &str| {
+            let raw = format!(
+                "GET {} HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\n{}Connection: close\r\n\r\n",
+                path, extra
+            );
+            String::from_utf8_lossy(&exchange(&address, &raw)).into_owned()
+        };
+
+        let compressed = get("/plain", "");
+        assert!(compressed.contains("Content-Encoding: gzip"), "{}", compressed);
+        let untouched = get("/no-transform", "");
+        assert!(!untouched.contains("Content-Encoding"), "{}", untouched);
+        assert!(untouched.contains("ETag: \"v1\""), "{}", untouched);
+        assert!(untouched.ends_with(&"hello ".repeat(100)));
+        // 请求中的 no-transform 同样生效
+        let requested = get("/plain", "Cache-Control: no-transform\r\n");
+        assert!(!requested.contains("Content-Encoding"), "{}", requested);
+        handle.shutdown();
+    }
+
+    #[test]
+    fn oversized_body_gets_a_readable_413() {
+        let mut server = HttpServer::new("127.0.0.1:0".into());
+        server.max_request_body(Some(1024));
+        server.add_handler(HttpMethod::POST, "/upload".into(), |ctx| ctx.set_response(HttpResponse::new(204)));
+        let handle = server.shutdown_handle();
+        let address = serve_in_background(server);
+
+        let mut client = TcpStream::connect(&address).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        client
+            .write_all(b"POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 200000\r\n\r\n")
+            .unwrap();
+        // 服务器已经回复 413, 客户端还在发送请求体; 服务器直接关闭的话这些数据换来 RST, 之后的写入失败
+        thread::sleep(Duration::from_millis(100));
+        for _ in 0..4 {
+            client.write_all(&[b'x'; 8 * 1024]).unwrap();
+            thread::sleep(Duration::from_millis(20));
+        }
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).unwrap();
+        let response = String::from_utf8_lossy(&response);
+        assert!(response.starts_with("HTTP/1.1 413 "), "{}", response);
+        assert!(response.ends_with("\r\n\r\n"), "{}", response);
+        handle.shutdown();
+    }
+
+    #[test]
+    fn keep_alive_parameters_are_advertised() {
+        let mut server = HttpServer::new("127.0.0.1:0".into());
+        server.keep_alive_timeout(Duration::from_secs(7));
+        server.max_keep_alive_requests(2);
+        server.add_handler(HttpMethod::GET, "/".into(), |ctx| ctx.set_response(HttpResponse::new(204)));
+        let handle = server.shutdown_handle();
+        let address = serve_in_background(server);
+        let mut client = TcpStream::connect(&address).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut get = |extra: &str| {
+            let raw = format!("GET / HTTP/1.1\r\nHost: localhost\r\n{}\r\n", extra);
+            client.write_all(raw.as_bytes()).unwrap();
+            read_head(&mut client)
+        };
+
+        let first = get("");
+        assert!(first.contains("Keep-Alive: timeout=7, max=1\r\n"), "{}", first);
+        // 最后一个请求之后关闭, 不再带 Keep-Alive
+        let last = get("");
+        assert!(last.contains("Connection: close\r\n"), "{}", last);
+        assert!(!last.contains("Keep-Alive"), "{}", last);
+
+        let mut client = TcpStream::connect(&address).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+        let closed = read_head(&mut client);
+        assert!(closed.starts_with("HTTP/1.1 204"), "{}", closed);
+        assert!(!closed.contains("Keep-Alive"), "{}", closed);
+        handle.shutdown();
+    }
+
+    #[test]
+    fn body_bytes_keeps_binary_payloads() {
+        let payload: Vec<u8> = vec![0x89, b'P', b'N', b'G', 0x00, 0xff, 0xfe, 0x80];
+        let mut raw = format!("POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n", payload.len())
+            .into_bytes();
+        raw.extend_from_slice(&payload);
+        let request = parse(&raw, None).unwrap();
+        assert!(matches!(request.body_bytes().unwrap(), Cow::Borrowed(bytes) if bytes == payload.as_slice()));
+
+        let spool = SpoolConfig {
+            threshold: 4,
+            dir: std::env::temp_dir(),
+        };
+        let spooled = parse_http_request(&mut &raw[..], "127.0.0.1:50000".into(), Some(&spool), None, &[]).unwrap();
+        assert!(spooled.body.is_none());
+        assert_eq!(spooled.body_bytes().unwrap().as_ref(), payload.as_slice());
+    }
+
+    #[test]
+    fn text_is_decoded_by_charset() {
+        let request = |content_type: &str, body: &[u8]| {
+            let mut raw = format!(
+                "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+                content_type,
+                body.len()
+            )
+            .into_bytes();
+            raw.extend_from_slice(body);
+            parse(&raw, None).unwrap()
+        };
+
+        assert_eq!(request("text/plain", "café".as_bytes()).text().unwrap(), "café");
+        assert_eq!(request("text/plain; charset=ISO-8859-1", b"caf\xe9").text().unwrap(), "café");
+        assert_eq!(request("text/plain; charset=\"us-ascii\"", b"cafe").text().unwrap(), "cafe");
+        assert!(matches!(request("text/plain; charset=us-ascii", b"caf\xe9").text(), Err(Error::Parse(_))));
+        assert!(matches!(request("text/plain", b"caf\xe9").text(), Err(Error::Parse(_))));
+        assert!(matches!(
+            request("text/plain; charset=Shift_JIS", b"abc").text(),
+            Err(Error::UnsupportedCharset(charset)) if charset == "Shift_JIS"
+        ));
+    }
+
+    #[test]
+    fn timed_handler_threads_are_capped_and_extensions_survive() {
+        struct Tag(&'static str);
+        let calls = Arc::new(AtomicUsize::new(0));
+        let (release, blocked) = mpsc::channel::<()>();
+        let blocked = Mutex::new(blocked);
+        let handler_calls = Arc::clone(&calls);
+        let mut server = HttpServer::new("127.0.0.1:0".into());
+        server
+            .add_handler(HttpMethod::GET, "/slow".into(), move |ctx| {
+                handler_calls.fetch_add(1, atomic::Ordering::SeqCst);
+                assert_eq!(ctx.get::<Tag>().map(|t| t.0), Some("outer"));
+                let _ = blocked.lock().unwrap().recv();
+                ctx.set_response(HttpResponse::new(200));
+            })
+            .timeout(Duration::from_millis(50))
+            .timeout_threads(1);
+        // 外层中间件在 chain.next 之后仍能读到自己放入的值
+        server.add_middleware(Middleware::new(|chain, ctx| {
+            ctx.set(Tag("outer"));
+            chain.next(ctx);
+            let tag = ctx.get::<Tag>().map(|t| t.0).unwrap_or("lost");
+            if let Some(response) = ctx.response.take() {
+                ctx.set_response(response.add_header("X-Tag".into(), tag.into()));
+            }
+        }));
+
+        let timed_out = server.call(get("/slow"));
+        assert_eq!(timed_out.status_code, 503);
+        assert_eq!(timed_out.headers.as_ref().unwrap()["X-Tag"], "outer");
+        // 第一个处理线程还卡着, 名额已满, 不再开新线程
+        let rejected = server.call(get("/slow"));
+        assert_eq!(rejected.status_code, 503);
+        assert_eq!(rejected.headers.as_ref().unwrap()["X-Tag"], "outer");
+        assert_eq!(calls.load(atomic::Ordering::SeqCst), 1);
+
+        release.send(()).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while server.handlers[0].timeout_threads.running.load(atomic::Ordering::SeqCst) > 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        release.send(()).unwrap();
+        assert_eq!(server.call(get("/slow")).status_code, 200);
+        assert_eq!(calls.load(atomic::Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn timed_handler_panic_keeps_extensions() {
+        struct Tag;
+        let mut server = HttpServer::new("127.0.0.1:0".into());
+        server.add_handler(HttpMethod::GET, "/panic".into(), |_| panic!("boom")).timeout(Duration::from_secs(5));
+        server.add_middleware(Middleware::new(|chain, ctx| {
+            ctx.set(Tag);
+            chain.next(ctx);
+            assert!(ctx.get::<Tag>().is_some());
+        }));
+        assert_eq!(server.call(get("/panic")).status_code, 500);
+    }
+
+    #[test]
+    fn static_dir_etag_policy_overrides_server_policy() {
+        let root = std::env::temp_dir().join(format!("static-etag-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("a.txt"), "hello").unwrap();
+        let root_str = root.to_string_lossy().into_owned();
+        let mut server = HttpServer::new("127.0.0.1:0".into());
+        server.etag_policy("/**".into(), EtagPolicy::Off);
+        server.serve_dir("/strong".into(), root_str.clone()).with_etag(EtagPolicy::Strong);
+        server.serve_dir("/weak".into(), root_str.clone()).with_etag(EtagPolicy::Weak);
+        server.serve_dir("/default".into(), root_str);
+        let address = serve_in_background(server);
+        let etag = |path: &str| {
+            let raw = format!("GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path);
+            let response = String::from_utf8(exchange(&address, &raw)).unwrap();
+            response.lines().find_map(|line| line.strip_prefix("ETag: ").map(str::to_string))
+        };
+
+        assert_eq!(etag("/strong/a.txt"), Some(etag::strong_etag(b"hello")));
+        assert!(etag("/weak/a.txt").unwrap().starts_with("W/"));
+        assert_eq!(etag("/default/a.txt"), None);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn panicking_error_page_and_fallback_keep_the_status() {
+        let mut server = HttpServer::new("127.0.0.1:0".into());
+        server.set_error_handler(404, |_| panic!("error page fails"));
+        server.static_fallback("/files/**".into(), |_| panic!("fallback fails"));
+        server.add_handler(HttpMethod::GET, "/files/missing".into(), |ctx| {
+            ctx.set_response(HttpResponse::file("./no-such-dir/missing.txt".into()))
+        });
+        let address = serve_in_background(server);
+        for path in ["/nothing-here", "/files/missing"] {
+            let raw = format!("GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path);
+            let response = String::from_utf8_lossy(&exchange(&address, &raw)).into_owned();
+            assert!(response.starts_with("HTTP/1.1 404 "), "{}: {}", path, response);
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use crate::{format_now, Context};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::time::Duration;
+
+// 服务器日志, 文本或每行一个 JSON 对象(便于 ELK/Loki 直接采集)
+// 格式进程内全局生效, 由 HttpServer::log_format 设置
+
+/// 日志格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// [时间]: 消息
+    Text,
+    /// {"timestamp": .., "level": .., "message": ..}, 访问日志另有 request_id、route、status、latency_ms 等字段
+    Json,
+}
+
+/// 日志级别, 低于最低级别的日志不输出
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    /// 不区分大小写, 如 "warn"
+    pub fn parse(s: &str) -> Option<Level> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "info" => Some(Level::Info),
+            "warn" => Some(Level::Warn),
+            "error" => Some(Level::Error),
+            _ => None,
+        }
+    }
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+        }
+    }
+}
+
+static JSON: AtomicBool = AtomicBool::new(false);
+static MIN_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+pub fn set_format(format: LogFormat) {
+    JSON.store(format == LogFormat::Json, Ordering::Relaxed);
+}
+
+pub fn format() -> LogFormat {
+    if JSON.load(Ordering::Relaxed) {
+        LogFormat::Json
+    } else {
+        LogFormat::Text
+    }
+}
+
+pub fn set_level(level: Level) {
+    MIN_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn level() -> Level {
+    match MIN_LEVEL.load(Ordering::Relaxed) {
+        0 => Level::Info,
+        1 => Level::Warn,
+        _ => Level::Error,
+    }
+}
+
+pub fn info(message: &str) {
+    write(Level::Info, message, &[]);
+}
+
+pub fn warn(message: &str) {
+    write(Level::Warn, message, &[]);
+}
+
+pub fn error(message: &str) {
+    write(Level::Error, message, &[]);
+}
+
+enum Value<'a> {
+    Str(&'a str),
+    Num(u128),
+    Null,
+}
+
+// 访问日志, 响应写出后记录; 5xx 为 error, 4xx 为 warn
+pub(crate) fn access(ctx: &Context, latency: Duration) {
+    let request = &ctx.request;
+    let status = ctx.response.as_ref().map_or(0, |r| r.status_code);
+    let level = match status {
+        500.. => Level::Error,
+        400.. => Level::Warn,
+        _ => Level::Info,
+    };
+    let method = format!("{:?}", request.method);
+    let message = format!(
+        "[{}] {} {} {} {}ms (queued {}ms) {}B/{}B {}",
+        request.id(),
+        method,
+        request.path,
+        status,
+        latency.as_millis(),
+        ctx.queue_wait().as_millis(),
+        ctx.bytes_read(),
+        ctx.bytes_written(),
+        request.remote_addr
+    );
+    write(
+        level,
+        &message,
+        &[
+            ("request_id", Value::Str(request.id())),
+            ("remote_addr", Value::Str(&request.remote_addr)),
+            ("method", Value::Str(&method)),
+            ("path", Value::Str(&request.path)),
+            ("route", ctx.route().map_or(Value::Null, Value::Str)),
+            ("status", Value::Num(status.into())),
+            ("latency_ms", Value::Num(latency.as_millis())),
+            ("queue_wait_ms", Value::Num(ctx.queue_wait().as_millis())),
+            ("bytes_read", Value::Num(ctx.bytes_read().into())),
+            ("bytes_written", Value::Num(ctx.bytes_written().into())),
+        ],
+    );
+}
+
+// 文本格式只输出消息, 字段已包含在消息中
+fn write(level: Level, message: &str, fields: &[(&str, Value)]) {
+    if level < self::level() {
+        return;
+    }
+    if format() == LogFormat::Text {
+        println!("[{}]: {}", format_now(), message);
+        return;
+    }
+    let mut line = format!(
+        r#"{{"timestamp":"{}","level":"{}","message":"{}""#,
+        format_now(),
+        level.as_str(),
+        escape(message)
+    );
+    for (key, value) in fields {
+        match value {
+            Value::Str(s) => line.push_str(&format!(r#","{}":"{}""#, key, escape(s))),
+            Value::Num(n) => line.push_str(&format!(r#","{}":{}"#, key, n)),
+            Value::Null => line.push_str(&format!(r#","{}":null"#, key)),
+        }
+    }
+    line.push('}');
+    println!("{}", line);
+}
+
+// 转义为 JSON 字符串内容, 不含两侧引号
+pub(crate) fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
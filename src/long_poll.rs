@@ -0,0 +1,204 @@
+use crate::HttpResponse;
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+// 进程内的通知总线, 供长轮询使用; clone 出来的句柄共享同一条总线
+// let bus = LongPoll::new().max_waiters(512);
+// handler: ctx.set_response(bus.wait_response("orders", Duration::from_secs(30)));
+// 其他 handler / 后台线程: bus.notify("orders", r#"{"id": 1}"#.into());
+
+const DEFAULT_MAX_KEYS: usize = 1024;
+const DEFAULT_MAX_WAITERS: usize = 1024;
+
+struct Topic {
+    seq: u64,
+    data: String,
+    // 每个 key 一个 Condvar, notify 只唤醒等这个 key 的线程
+    changed: Arc<Condvar>,
+    waiters: usize,
+    last_used: Instant,
+}
+
+#[derive(Default)]
+struct Bus {
+    topics: HashMap<String, Topic>,
+    // 全局递增的序号, key 被淘汰后重新出现也不会回退
+    seq: u64,
+    waiters: usize,
+}
+
+/// 长轮询的通知总线, 按 key 分发事件
+#[derive(Clone)]
+pub struct LongPoll {
+    bus: Arc<Mutex<Bus>>,
+    max_keys: usize,
+    max_waiters: usize,
+}
+
+/// wait_since 的结果
+#[derive(Debug, PartialEq, Eq)]
+pub enum Wait {
+    /// 序号与数据
+    Event(u64, String),
+    TimedOut,
+    /// 等待者已达上限
+    Busy,
+}
+
+impl Default for LongPoll {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LongPoll {
+    pub fn new() -> Self {
+        LongPoll { bus: Arc::new(Mutex::new(Bus::default())), max_keys: DEFAULT_MAX_KEYS, max_waiters: DEFAULT_MAX_WAITERS }
+    }
+
+    /// 最多保留的 key 数, 默认 1024, 超出时淘汰最久没用过且没人等待的 key
+    pub fn max_keys(mut self, max: usize) -> Self {
+        self.max_keys = max.max(1);
+        self
+    }
+
+    /// 同时等待的请求数上限, 默认 1024, 超出时 wait_response 回复 503
+    pub fn max_waiters(mut self, max: usize) -> Self {
+        self.max_waiters = max;
+        self
+    }
+
+    /// 返回本次事件的序号
+    pub fn notify(&self, key: &str, data: String) -> u64 {
+        let mut bus = self.bus.lock().unwrap();
+        bus.seq += 1;
+        let seq = bus.seq;
+        let topic = self.topic(&mut bus, key);
+        topic.seq = seq;
+        topic.data = data;
+        topic.changed.notify_all();
+        seq
+    }
+
+    /// 当前序号, 客户端带着它再来轮询就不会漏掉两次请求之间的事件
+    pub fn current_seq(&self, key: &str) -> u64 {
+        self.bus.lock().unwrap().topics.get(key).map(|t| t.seq).unwrap_or(0)
+    }
+
+    /// 等待序号大于 since 的事件
+    pub fn wait_since(&self, key: &str, since: u64, timeout: Duration) -> Wait {
+        let deadline = Instant::now() + timeout;
+        let mut bus = self.bus.lock().unwrap();
+        if let Some(topic) = bus.topics.get(key).filter(|t| t.seq > since) {
+            return Wait::Event(topic.seq, topic.data.clone());
+        }
+        if bus.waiters >= self.max_waiters {
+            return Wait::Busy;
+        }
+        bus.waiters += 1;
+        let topic = self.topic(&mut bus, key);
+        topic.waiters += 1;
+        let changed = Arc::clone(&topic.changed);
+        let result = loop {
+            let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
+                break Wait::TimedOut;
+            };
+            bus = changed.wait_timeout(bus, remaining).unwrap().0;
+            // 有等待者的 key 不会被淘汰
+            let topic = &bus.topics[key];
+            if topic.seq > since {
+                break Wait::Event(topic.seq, topic.data.clone());
+            }
+        };
+        bus.waiters -= 1;
+        let topic = bus.topics.get_mut(key).unwrap();
+        topic.waiters -= 1;
+        topic.last_used = Instant::now();
+        result
+    }
+
+    /// 只等待调用之后发生的事件, 超时或等待者已满时为 None
+    pub fn wait_for(&self, key: &str, timeout: Duration) -> Option<String> {
+        match self.wait_since(key, self.current_seq(key), timeout) {
+            Wait::Event(_, data) => Some(data),
+            Wait::TimedOut | Wait::Busy => None,
+        }
+    }
+
+    /// 有事件返回 200 + 数据(JSON), 超时返回 204, 等待者已满返回 503, 序号放在 X-Event-Seq 中
+    pub fn wait_response(&self, key: &str, timeout: Duration) -> HttpResponse {
+        match self.wait_since(key, self.current_seq(key), timeout) {
+            Wait::Event(seq, data) => HttpResponse::json(data).add_header("X-Event-Seq".into(), seq.to_string()),
+            Wait::TimedOut => HttpResponse::new(204),
+            Wait::Busy => HttpResponse::new(503),
+        }
+    }
+
+    // 取出或新建 key, 新建前 key 数已满时淘汰最久没用过且没人等待的 key
+    fn topic<'a>(&self, bus: &'a mut Bus, key: &str) -> &'a mut Topic {
+        if !bus.topics.contains_key(key) && bus.topics.len() >= self.max_keys {
+            let idle = bus.topics.iter().filter(|(_, t)| t.waiters == 0).min_by_key(|(_, t)| t.last_used).map(|(k, _)| k.clone());
+            if let Some(idle) = idle {
+                bus.topics.remove(&idle);
+            }
+        }
+        let topic = bus.topics.entry(key.to_string()).or_insert_with(|| Topic {
+            seq: 0,
+            data: String::new(),
+            changed: Arc::new(Condvar::new()),
+            waiters: 0,
+            last_used: Instant::now(),
+        });
+        topic.last_used = Instant::now();
+        topic
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn notify_wakes_waiters_of_the_key() {
+        let bus = LongPoll::new();
+        let waiter = bus.clone();
+        let handle = thread::spawn(move || waiter.wait_for("orders", Duration::from_secs(5)));
+        while bus.bus.lock().unwrap().waiters == 0 {
+            thread::sleep(Duration::from_millis(5));
+        }
+        bus.notify("other", "ignored".into());
+        bus.notify("orders", "1".into());
+        assert_eq!(handle.join().unwrap().as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn wait_since_returns_missed_events() {
+        let bus = LongPoll::new();
+        let since = bus.current_seq("orders");
+        let seq = bus.notify("orders", "1".into());
+        assert_eq!(bus.wait_since("orders", since, Duration::ZERO), Wait::Event(seq, "1".into()));
+        assert_eq!(bus.wait_since("orders", seq, Duration::from_millis(20)), Wait::TimedOut);
+    }
+
+    #[test]
+    fn timeout_and_busy_responses() {
+        let bus = LongPoll::new().max_waiters(0);
+        assert_eq!(bus.wait_response("orders", Duration::from_millis(20)).status_code, 503);
+        let bus = LongPoll::new();
+        assert_eq!(bus.wait_response("orders", Duration::from_millis(20)).status_code, 204);
+    }
+
+    #[test]
+    fn idle_keys_are_evicted() {
+        let bus = LongPoll::new().max_keys(2);
+        bus.notify("a", "1".into());
+        thread::sleep(Duration::from_millis(2));
+        bus.notify("b", "2".into());
+        let seq = bus.notify("c", "3".into());
+        assert_eq!(bus.current_seq("a"), 0);
+        assert_eq!(bus.current_seq("c"), seq);
+        assert_eq!(bus.bus.lock().unwrap().topics.len(), 2);
+    }
+}
@@ -0,0 +1,143 @@
+// Content-Type / Accept 中的媒体类型, 如 `text/html; charset=UTF-8`
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaType {
+    pub main_type: String,
+    pub sub_type: String,
+    pub params: Vec<(String, String)>,
+}
+
+impl MediaType {
+    pub fn new(main_type: &str, sub_type: &str) -> MediaType {
+        MediaType {
+            main_type: main_type.to_ascii_lowercase(),
+            sub_type: sub_type.to_ascii_lowercase(),
+            params: Vec::new(),
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<MediaType> {
+        let mut parts = split_params(value).into_iter();
+        let essence = parts.next()?;
+        let (main_type, sub_type) = essence.trim().split_once('/')?;
+        let (main_type, sub_type) = (main_type.trim(), sub_type.trim());
+        if !is_token(main_type) || !is_token(sub_type) {
+            return None;
+        }
+        let mut media_type = MediaType::new(main_type, sub_type);
+        for part in parts {
+            // 忽略不合法的参数, 而不是整体失败
+            let Some((name, value)) = part.split_once('=') else {
+                continue;
+            };
+            let name = name.trim();
+            if !is_token(name) {
+                continue;
+            }
+            media_type
+                .params
+                .push((name.to_ascii_lowercase(), unquote(value.trim())));
+        }
+        Some(media_type)
+    }
+
+    // 参数名大小写不敏感
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn charset(&self) -> Option<&str> {
+        self.param("charset")
+    }
+
+    pub fn boundary(&self) -> Option<&str> {
+        self.param("boundary")
+    }
+
+    // 不带参数的 type/subtype
+    pub fn essence(&self) -> String {
+        format!("{}/{}", self.main_type, self.sub_type)
+    }
+
+    pub fn is(&self, essence: &str) -> bool {
+        self.essence().eq_ignore_ascii_case(essence)
+    }
+
+    // 支持 */* 与 type/* 通配, 任意一方为通配都算匹配
+    pub fn matches(&self, other: &MediaType) -> bool {
+        let main_ok = self.main_type == "*" || other.main_type == "*" || self.main_type == other.main_type;
+        let sub_ok = self.sub_type == "*" || other.sub_type == "*" || self.sub_type == other.sub_type;
+        main_ok && sub_ok
+    }
+}
+
+impl std::fmt::Display for MediaType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.main_type, self.sub_type)?;
+        for (name, value) in self.params.iter() {
+            if is_token(value) {
+                write!(f, "; {}={}", name, value)?;
+            } else {
+                write!(f, "; {}=\"{}\"", name, value.replace('\\', "\\\\").replace('"', "\\\""))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// 按 ; 切分, 但跳过引号内的 ;
+fn split_params(value: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for c in value.chars() {
+        if escaped {
+            current.push(c);
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' if in_quotes => {
+                current.push(c);
+                escaped = true;
+            }
+            '"' => {
+                current.push(c);
+                in_quotes = !in_quotes;
+            }
+            ';' if !in_quotes => parts.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    parts.push(current);
+    parts
+}
+
+fn unquote(value: &str) -> String {
+    let Some(inner) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) else {
+        return value.to_string();
+    };
+    let mut result = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(next) = chars.next() {
+                result.push(next);
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+// RFC 7230 token, 也用于校验响应头名称
+pub(crate) fn is_token(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c))
+}
@@ -22,6 +22,7 @@ static CONTENT_TYPE_MAP: LazyLock<HashMap<&'static str, &'static str>> = LazyLoc
     map.insert("bmp", "image/bmp");
     map.insert("svg", "image/svg+xml");
     map.insert("webp", "image/webp");
+    map.insert("ico", "image/x-icon");
 
     // 字体类型
     map.insert("ttf", "font/ttf");
@@ -49,10 +50,10 @@ static CONTENT_TYPE_MAP: LazyLock<HashMap<&'static str, &'static str>> = LazyLoc
 });
 
 pub fn get_content_type(file_path: &str) -> &str {
-    if let Some(extension) = file_path.split('.').last() {
-        if let Some(content_type) = CONTENT_TYPE_MAP.get(extension) {
-            return content_type;
-        }
+    if let Some(extension) = file_path.rsplit('.').next()
+        && let Some(content_type) = CONTENT_TYPE_MAP.get(extension)
+    {
+        return content_type;
     }
     "application/octet-stream"
 }
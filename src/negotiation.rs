@@ -0,0 +1,140 @@
+use crate::media_type::MediaType;
+
+// Accept 系列请求头中的一项, 如 `text/html;q=0.8`
+#[derive(Debug, Clone, PartialEq)]
+pub struct QualityItem {
+    pub value: String,
+    pub quality: f32,
+}
+
+// 解析并按 q 值降序排列, q 相同时保持请求头中的原始顺序
+pub fn parse_quality_list(header: &str) -> Vec<QualityItem> {
+    let mut items = header
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let value = parts.next()?.trim();
+            if value.is_empty() {
+                return None;
+            }
+            let mut quality = 1.0;
+            let mut params = Vec::new();
+            for param in parts {
+                match param.trim().split_once('=') {
+                    Some((name, q)) if name.trim().eq_ignore_ascii_case("q") => {
+                        quality = q.trim().parse::<f32>().ok()?.clamp(0.0, 1.0);
+                    }
+                    _ => params.push(param.trim()),
+                }
+            }
+            // 媒体类型参数(如 level=1)保留在 value 中
+            let value = if params.is_empty() {
+                value.to_string()
+            } else {
+                format!("{};{}", value, params.join(";"))
+            };
+            Some(QualityItem { value, quality })
+        })
+        .collect::<Vec<QualityItem>>();
+    items.sort_by(|a, b| b.quality.total_cmp(&a.quality));
+    items
+}
+
+// 根据 Accept 从服务端可提供的类型中选择最合适的一个
+// available 的顺序即服务端偏好, q 值相同时取靠前的
+pub fn preferred_media_type<'a>(accept: &str, available: &[&'a str]) -> Option<&'a str> {
+    let ranges = parse_quality_list(accept)
+        .into_iter()
+        .filter_map(|item| Some((MediaType::parse(&item.value)?, item.quality)))
+        .collect::<Vec<(MediaType, f32)>>();
+    pick_best(available, |candidate| {
+        let candidate = MediaType::parse(candidate)?;
+        // 最具体的匹配项决定 q 值: type/subtype > type/* > */*
+        ranges
+            .iter()
+            .filter(|(range, _)| range.matches(&candidate))
+            .max_by_key(|(range, _)| media_range_specificity(range))
+            .map(|(_, quality)| *quality)
+    })
+}
+
+// 根据 Accept-Encoding 选择编码, identity 除非被显式排除否则总是可接受
+pub fn preferred_encoding<'a>(accept_encoding: &str, available: &[&'a str]) -> Option<&'a str> {
+    let codings = parse_quality_list(accept_encoding);
+    pick_best(available, |candidate| {
+        let exact = codings.iter().find(|c| c.value.eq_ignore_ascii_case(candidate));
+        let any = codings.iter().find(|c| c.value == "*");
+        match (exact, any) {
+            (Some(c), _) | (None, Some(c)) => Some(c.quality),
+            (None, None) if candidate.eq_ignore_ascii_case("identity") => Some(0.001),
+            (None, None) => None,
+        }
+    })
+}
+
+// 根据 Accept-Language 选择语言, 采用 RFC 4647 的基本过滤: `en` 可匹配 `en-US`
+pub fn preferred_language<'a>(accept_language: &str, available: &[&'a str]) -> Option<&'a str> {
+    let ranges = parse_quality_list(accept_language);
+    pick_best(available, |candidate| {
+        ranges
+            .iter()
+            .filter(|range| language_matches(&range.value, candidate))
+            .max_by_key(|range| if range.value == "*" { 0 } else { range.value.len() })
+            .map(|range| range.quality)
+    })
+}
+
+// range 与 tag 都可能来自请求头, 含非 ASCII 字符时按字节切分可能落在字符中间, 因此用 get 而不是下标
+pub fn language_matches(range: &str, tag: &str) -> bool {
+    if range == "*" {
+        return true;
+    }
+    tag.get(..range.len()).is_some_and(|prefix| prefix.eq_ignore_ascii_case(range))
+        && (tag.len() == range.len() || tag.as_bytes()[range.len()] == b'-')
+}
+
+fn media_range_specificity(range: &MediaType) -> usize {
+    match (range.main_type.as_str(), range.sub_type.as_str()) {
+        ("*", _) => 0,
+        (_, "*") => 1,
+        _ => 2 + range.params.len(),
+    }
+}
+
+// 返回 q 值最高且大于 0 的候选项
+fn pick_best<'a>(available: &[&'a str], quality_of: impl Fn(&str) -> Option<f32>) -> Option<&'a str> {
+    let mut best: Option<(&'a str, f32)> = None;
+    for candidate in available {
+        let Some(quality) = quality_of(candidate) else {
+            continue;
+        };
+        if quality <= 0.0 {
+            continue;
+        }
+        if best.is_none_or(|(_, q)| quality > q) {
+            best = Some((candidate, quality));
+        }
+    }
+    best.map(|(candidate, _)| candidate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn language_matches_prefix_on_subtag_boundary() {
+        assert!(language_matches("en", "en-US"));
+        assert!(language_matches("EN-us", "en-US"));
+        assert!(!language_matches("en", "eng"));
+        assert!(!language_matches("en-US", "en"));
+    }
+
+    #[test]
+    fn language_matches_does_not_panic_on_non_ascii() {
+        assert!(!language_matches("z", "中文"));
+        assert!(!language_matches("中", "zh-CN"));
+        assert!(language_matches("中文", "中文-CN"));
+        assert_eq!(preferred_language("中文, é;q=0.5", &["zh", "en"]), None);
+    }
+}
@@ -0,0 +1,106 @@
+use crate::url;
+use std::cmp::Ordering;
+
+// 按 / 分段匹配路径, 路由与中间件共用
+// `*` 与 `:name` 匹配恰好一段, `**` 匹配任意多段(包括零段), 如 /api/** 同时匹配 /api 与 /api/a/b
+// 段内的 `*` 匹配该段中任意个字符, 如 /**/*.php、/logs/app-*.log
+pub fn is_path_match(pattern: &str, path: &str) -> bool {
+    capture_params(pattern, path).is_some()
+}
+
+// 匹配成功时返回 `:name` 段捕获的值(已做百分号解码), 按模式中的顺序
+pub fn capture_params(pattern: &str, path: &str) -> Option<Vec<(String, String)>> {
+    let pattern_segments = segments(pattern);
+    let path_segments = segments(path);
+    let mut params = Vec::new();
+    if !match_segments(&pattern_segments, &path_segments, &mut params) {
+        return None;
+    }
+    Some(
+        params
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), url::percent_decode(value, false)))
+            .collect(),
+    )
+}
+
+pub(crate) fn segments(path: &str) -> Vec<&str> {
+    path.split('/').filter(|s| !s.is_empty()).collect()
+}
+
+fn match_segments<'a>(pattern: &[&'a str], path: &[&'a str], params: &mut Vec<(&'a str, &'a str)>) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| {
+            let captured = params.len();
+            if match_segments(rest, &path[skip..], params) {
+                return true;
+            }
+            // 回溯时丢掉这次尝试捕获的参数
+            params.truncate(captured);
+            false
+        }),
+        Some((segment, rest)) => match path.split_first() {
+            Some((first, path_rest)) => {
+                if let Some(name) = segment.strip_prefix(':') {
+                    params.push((name, first));
+                } else if *segment != "*" && !is_segment_match(segment, first) {
+                    return false;
+                }
+                match_segments(rest, path_rest, params)
+            }
+            None => false,
+        },
+    }
+}
+
+// 段内通配, `*` 不跨越 /
+pub(crate) fn is_segment_match(pattern: &str, segment: &str) -> bool {
+    if !pattern.contains('*') {
+        return pattern == segment;
+    }
+    let parts = pattern.split('*').collect::<Vec<&str>>();
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if segment.len() < first.len() + last.len() || !segment.starts_with(first) || !segment.ends_with(last) {
+        return false;
+    }
+    // 中间部分按顺序贪心地取最早的出现位置
+    let mut rest = &segment[first.len()..segment.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+// 比较两个模式的具体程度, Greater 表示 a 更具体
+// 逐段比较: 字面量 > 段内通配(如 *.php) > :param > * > 模式结束 > **, 所以 /a 比 /a/** 更具体
+pub fn compare_specificity(a: &str, b: &str) -> Ordering {
+    let a_ranks = segments(a).into_iter().map(segment_rank).chain([END_RANK]);
+    let b_ranks = segments(b).into_iter().map(segment_rank).chain([END_RANK]);
+    a_ranks.cmp(b_ranks)
+}
+
+const END_RANK: u8 = 1;
+
+fn segment_rank(segment: &str) -> u8 {
+    match segment {
+        "**" => 0,
+        "*" => 2,
+        _ if segment.starts_with(':') => 3,
+        _ if segment.contains('*') => 4,
+        _ => 5,
+    }
+}
+
+// 规范化后相同的模式匹配的路径集合也相同, 如 /users/:id 与 /users/:name/
+pub fn normalize(pattern: &str) -> String {
+    let normalized = segments(pattern)
+        .into_iter()
+        .map(|segment| if segment.starts_with(':') { ":" } else { segment })
+        .collect::<Vec<&str>>()
+        .join("/");
+    format!("/{}", normalized)
+}
@@ -0,0 +1,109 @@
+use crate::{HttpMethod, HttpRequest, HttpResponse};
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom};
+
+// Range 请求(RFC 9110 14.2), 只支持单个字节范围; 多个范围或格式不合法时忽略 Range, 返回完整内容
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RangeRequest {
+    Full,
+    // 起止位置, 都包含在内
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+// etag 为响应的 ETag; If-Range 与之强比较不一致时说明内容已变化, 返回完整内容
+pub(crate) fn requested_range(request: &HttpRequest, etag: Option<&str>, len: u64) -> RangeRequest {
+    if request.method != HttpMethod::GET {
+        return RangeRequest::Full;
+    }
+    let Some(range) = request.header("Range") else {
+        return RangeRequest::Full;
+    };
+    if let Some(if_range) = request.header("If-Range") {
+        let if_range = if_range.trim();
+        if if_range.starts_with("W/") || etag.is_none_or(|etag| etag.starts_with("W/") || etag != if_range) {
+            return RangeRequest::Full;
+        }
+    }
+    let Some(spec) = range.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Full;
+    };
+    if spec.contains(',') {
+        return RangeRequest::Full;
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return RangeRequest::Full;
+    };
+    let (start, end) = (start.trim(), end.trim());
+    // -n 为最后 n 个字节
+    if start.is_empty() {
+        return match end.parse::<u64>() {
+            Ok(0) => RangeRequest::Unsatisfiable,
+            Ok(_) if len == 0 => RangeRequest::Unsatisfiable,
+            Ok(suffix) => RangeRequest::Partial(len.saturating_sub(suffix), len - 1),
+            Err(_) => RangeRequest::Full,
+        };
+    }
+    let Ok(start) = start.parse::<u64>() else {
+        return RangeRequest::Full;
+    };
+    let end = match end {
+        "" => u64::MAX,
+        end => match end.parse::<u64>() {
+            Ok(end) if end >= start => end,
+            _ => return RangeRequest::Full,
+        },
+    };
+    if start >= len {
+        return RangeRequest::Unsatisfiable;
+    }
+    RangeRequest::Partial(start, end.min(len - 1))
+}
+
+// 内存中的 200 响应(含嵌入资源)按 Range 截取为 206, 范围无法满足时为 416
+pub(crate) fn apply_to_memory(request: &HttpRequest, response: &mut HttpResponse) {
+    // 压缩后的内容不支持 Range
+    if response.status_code != 200 || response.header("Content-Encoding").is_some() {
+        return;
+    }
+    response.set_header("Accept-Ranges", "bytes".into());
+    let len = response.memory_body().len() as u64;
+    match requested_range(request, response.header("ETag"), len) {
+        RangeRequest::Full => {}
+        RangeRequest::Partial(start, end) => {
+            let body = response.memory_body()[start as usize..=end as usize].to_vec();
+            response.status_code = 206;
+            response.set_header("Content-Range", format!("bytes {}-{}/{}", start, end, len));
+            response.body = Some(body);
+        }
+        RangeRequest::Unsatisfiable => {
+            *response = HttpResponse::new(416);
+            response.set_header("Content-Range", format!("bytes */{}", len));
+        }
+    }
+}
+
+// 文件的 200 响应按 Range 移动到起始位置并改为 206, 由调用方只发送范围内的字节; 无法满足时改为 416
+pub(crate) fn apply_to_file(request: &HttpRequest, response: &mut HttpResponse, file: &mut File) -> io::Result<RangeRequest> {
+    if response.status_code != 200 {
+        return Ok(RangeRequest::Full);
+    }
+    response.set_header("Accept-Ranges", "bytes".into());
+    let len = file.metadata()?.len();
+    let range = requested_range(request, response.header("ETag"), len);
+    match range {
+        RangeRequest::Full => {}
+        RangeRequest::Partial(start, end) => {
+            file.seek(SeekFrom::Start(start))?;
+            response.status_code = 206;
+            response.set_header("Content-Range", format!("bytes {}-{}/{}", start, end, len));
+            response.set_header("Content-Length", (end - start + 1).to_string());
+        }
+        RangeRequest::Unsatisfiable => {
+            *response = HttpResponse::new(416);
+            response.set_header("Content-Range", format!("bytes */{}", len));
+        }
+    }
+    Ok(range)
+}
@@ -0,0 +1,138 @@
+use crate::log::{self, Level, LogFormat};
+use crate::{shutdown, HttpServer};
+use std::fs;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+// 轮询配置文件的修改时间, 变化后重新读取并应用可热更新的设置
+// 设置都保存在原子变量中, 每个请求开始时读取, 正在处理的请求与已有连接不受影响
+// 开启 reload_on_sighup 时, 收到 SIGHUP 后不论修改时间都重新读取, 并重新加载 TLS 证书
+//
+// # 注释
+// log_level = warn
+// log_format = json
+// maintenance = false
+// max_connections_per_ip = 20    0 为不限制, 只影响之后接受的连接
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// 只能在 run 之前用代码设置的项, 出现在配置文件中时记录警告
+const NOT_RELOADABLE: [&str; 2] = ["worker_threads", "handler_threads"];
+
+// server 关闭后退出, run 中 unpark 后 join, 不必等满一个轮询间隔
+pub(crate) fn spawn(path: Option<String>, server: Arc<HttpServer>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut last_modified: Option<SystemTime> = None;
+        let mut hangups = shutdown::hangups();
+        while !server.shutdown.is_requested() {
+            let hangup = server.reload_on_sighup && shutdown::hangups() != hangups;
+            if hangup {
+                hangups = shutdown::hangups();
+                log::info("SIGHUP received, reloading");
+                reload_tls(&server);
+            }
+            if let Some(path) = path.as_ref() {
+                let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+                if modified.is_some() && (modified != last_modified || hangup) {
+                    last_modified = modified;
+                    match fs::read_to_string(path) {
+                        Ok(content) => apply(&content, &server),
+                        Err(e) => log::warn(&format!("reload {} failed: {}", path, e)),
+                    }
+                }
+            }
+            thread::park_timeout(POLL_INTERVAL);
+        }
+    })
+}
+
+#[cfg(feature = "tls")]
+fn reload_tls(server: &HttpServer) {
+    if let Some(tls) = server.tls.as_ref() {
+        match tls.reload() {
+            Ok(()) => log::info("tls certificate reloaded"),
+            Err(e) => log::warn(&format!("tls certificate not reloaded: {}", e)),
+        }
+    }
+}
+
+#[cfg(not(feature = "tls"))]
+fn reload_tls(_server: &HttpServer) {}
+
+// 先整体校验, 有任何错误时不应用, 避免只生效一半
+fn apply(content: &str, server: &HttpServer) {
+    let mut level = None;
+    let mut format = None;
+    let mut maintenance = None;
+    let mut max_connections_per_ip = None;
+    for (n, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parsed = line.split_once('=').and_then(|(key, value)| {
+            let value = value.trim();
+            match key.trim() {
+                "log_level" => level = Some(Level::parse(value)?),
+                "log_format" => {
+                    format = Some(match value {
+                        "text" => LogFormat::Text,
+                        "json" => LogFormat::Json,
+                        _ => return None,
+                    })
+                }
+                "maintenance" => maintenance = Some(value.parse::<bool>().ok()?),
+                "max_connections_per_ip" => max_connections_per_ip = Some(value.parse::<usize>().ok()?),
+                // 线程池在 run 开始时创建, 改了也不会生效, 不能静默地忽略或覆盖代码中的设置
+                key if NOT_RELOADABLE.contains(&key) => {
+                    log::warn(&format!("{} in config file ignored, it can only be set before run", key));
+                }
+                _ => return None,
+            }
+            Some(())
+        });
+        if parsed.is_none() {
+            log::warn(&format!("config not reloaded, invalid line {}: {}", n + 1, line));
+            return;
+        }
+    }
+    if let Some(level) = level {
+        log::set_level(level);
+    }
+    if let Some(format) = format {
+        log::set_format(format);
+    }
+    if let Some(maintenance) = maintenance {
+        server.maintenance.store(maintenance, Ordering::Relaxed);
+    }
+    if let Some(limit) = max_connections_per_ip {
+        server.max_connections_per_ip.store(limit, Ordering::Relaxed);
+    }
+    log::info("config reloaded");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limit_is_reloaded_and_thread_counts_are_not() {
+        let mut server = HttpServer::new("127.0.0.1:0".into());
+        server.worker_threads(4);
+        apply("max_connections_per_ip = 20\nworker_threads = 16\n", &server);
+        assert_eq!(server.max_connections_per_ip.load(Ordering::Relaxed), 20);
+        assert_eq!(server.pool_size(), 4);
+        apply("max_connections_per_ip = 0\n", &server);
+        assert_eq!(server.max_connections_per_ip.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn invalid_line_keeps_previous_settings() {
+        let server = HttpServer::new("127.0.0.1:0".into());
+        apply("maintenance = true\nmax_connections_per_ip = many\n", &server);
+        assert!(!server.maintenance.load(Ordering::Relaxed));
+        assert_eq!(server.max_connections_per_ip.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,82 @@
+use crate::path_pattern::{is_segment_match, segments};
+use std::collections::HashMap;
+
+// 按路径段组织的路由前缀树, 只负责找出路径能匹配的路由,
+// 最终选哪一个仍按 compare_specificity 与注册顺序决定, 与逐个比较的结果一致
+
+#[derive(Debug, Default)]
+pub struct Router {
+    root: Node,
+}
+
+#[derive(Debug, Default)]
+struct Node {
+    // 在此结束的路由, 值为注册序号
+    routes: Vec<usize>,
+    literals: HashMap<String, Node>,
+    // 段内通配, 如 *.php
+    globs: Vec<(String, Node)>,
+    // :name 与 * 都匹配恰好一段
+    single: Option<Box<Node>>,
+    multi: Option<Box<Node>>,
+}
+
+impl Router {
+    pub fn insert(&mut self, pattern: &str, index: usize) {
+        let mut node = &mut self.root;
+        for segment in segments(pattern) {
+            node = match segment {
+                "**" => node.multi.get_or_insert_with(Default::default),
+                "*" => node.single.get_or_insert_with(Default::default),
+                _ if segment.starts_with(':') => node.single.get_or_insert_with(Default::default),
+                _ if segment.contains('*') => {
+                    let i = match node.globs.iter().position(|(glob, _)| glob == segment) {
+                        Some(i) => i,
+                        None => {
+                            node.globs.push((segment.to_string(), Node::default()));
+                            node.globs.len() - 1
+                        }
+                    };
+                    &mut node.globs[i].1
+                }
+                _ => node.literals.entry(segment.to_string()).or_default(),
+            };
+        }
+        node.routes.push(index);
+    }
+
+    // 能匹配 path 的路由注册序号, 升序
+    pub fn candidates(&self, path: &str) -> Vec<usize> {
+        let mut found = Vec::new();
+        self.root.collect(&segments(path), &mut found);
+        found.sort_unstable();
+        found.dedup();
+        found
+    }
+}
+
+impl Node {
+    fn collect(&self, path: &[&str], found: &mut Vec<usize>) {
+        if let Some(multi) = self.multi.as_ref() {
+            // ** 可以吃掉 0 到全部剩余的段
+            for skip in 0..=path.len() {
+                multi.collect(&path[skip..], found);
+            }
+        }
+        let Some((first, rest)) = path.split_first() else {
+            found.extend_from_slice(&self.routes);
+            return;
+        };
+        if let Some(child) = self.literals.get(*first) {
+            child.collect(rest, found);
+        }
+        for (glob, child) in self.globs.iter() {
+            if is_segment_match(glob, first) {
+                child.collect(rest, found);
+            }
+        }
+        if let Some(single) = self.single.as_ref() {
+            single.collect(rest, found);
+        }
+    }
+}
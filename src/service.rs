@@ -0,0 +1,52 @@
+use crate::{HttpRequest, HttpResponse, HttpServer};
+use std::sync::Arc;
+use std::time::Duration;
+
+// 从解析好的请求到响应的处理过程, 与连接、线程池无关, 便于测试或嵌入到其他服务器中
+// TCP 连接上的请求走的也是同一套 dispatch 流程
+
+/// 把一个请求处理为一个响应
+///
+/// HttpServer 的实现依次经过 Host 校验、中间件、路由与 forward, 与 run() 收到请求时相同:
+///
+/// ```
+/// use rustbook_httpserver::{HttpMethod, HttpRequest, HttpResponse, HttpServer, Service};
+///
+/// let mut server = HttpServer::new("127.0.0.1:0".into());
+/// server.add_handler(HttpMethod::GET, "/ping".into(), |ctx| {
+///     ctx.set_response(HttpResponse::bytes("text/plain".into(), b"pong".to_vec()))
+/// });
+/// let raw = b"GET /ping HTTP/1.1\r\nHost: localhost\r\n\r\n";
+/// let request = HttpRequest::parse(&mut &raw[..], "127.0.0.1:50000".into()).unwrap();
+/// let response = server.call(request);
+/// assert_eq!(response.status_code, 200);
+/// assert_eq!(response.body.as_deref(), Some(&b"pong"[..]));
+/// ```
+pub trait Service: Send + Sync {
+    fn call(&self, request: HttpRequest) -> HttpResponse;
+}
+
+/// view 与 file 响应在这里还没有打开文件, 错误页、压缩、Range 等也只在写到连接上时处理;
+/// 中间件既没有设置响应也没有调用后续处理时为 500
+impl Service for HttpServer {
+    fn call(&self, request: HttpRequest) -> HttpResponse {
+        self.dispatch_request(request, Duration::ZERO)
+            .response
+            .unwrap_or_else(|| HttpResponse::new(500))
+    }
+}
+
+impl<F> Service for F
+where
+    F: Fn(HttpRequest) -> HttpResponse + Send + Sync,
+{
+    fn call(&self, request: HttpRequest) -> HttpResponse {
+        self(request)
+    }
+}
+
+impl<S: Service + ?Sized> Service for Arc<S> {
+    fn call(&self, request: HttpRequest) -> HttpResponse {
+        (**self).call(request)
+    }
+}
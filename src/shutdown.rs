@@ -0,0 +1,198 @@
+use crate::log;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// 优雅关闭: 停止接受新连接, 已接受的连接处理完当前请求后关闭, 线程池 drop 时等待进行中的请求结束
+// accept 会一直阻塞, 关闭时向自己发起一个连接把它唤醒
+
+/// 从其他线程停止 HttpServer::run, 可以 clone 后在多处使用
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    state: Arc<ShutdownState>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct ShutdownState {
+    requested: AtomicBool,
+    // run 绑定后的实际地址
+    address: Mutex<Option<SocketAddr>>,
+}
+
+impl ShutdownHandle {
+    pub(crate) fn new(state: Arc<ShutdownState>) -> ShutdownHandle {
+        ShutdownHandle { state }
+    }
+    /// 请求关闭, 立即返回; run 在进行中的请求结束(或超过 HttpServer::shutdown_timeout)后返回
+    pub fn shutdown(&self) {
+        if self.state.requested.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        log::info("shutdown requested");
+        if let Some(mut address) = *self.state.address.lock().unwrap() {
+            if address.ip().is_unspecified() {
+                address.set_ip(match address {
+                    SocketAddr::V4(_) => [127, 0, 0, 1].into(),
+                    SocketAddr::V6(_) => std::net::Ipv6Addr::LOCALHOST.into(),
+                });
+            }
+            let _ = TcpStream::connect_timeout(&address, Duration::from_secs(1));
+        }
+    }
+    pub fn is_shutdown(&self) -> bool {
+        self.state.is_requested()
+    }
+}
+
+impl ShutdownState {
+    pub(crate) fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+    pub(crate) fn bound(&self, address: SocketAddr) {
+        *self.address.lock().unwrap() = Some(address);
+    }
+}
+
+// 信号处理函数中只能做很少的事, 这里只设置标志, 由单独的线程轮询后发起关闭
+static SIGNALED: AtomicBool = AtomicBool::new(false);
+// 收到 SIGHUP 的次数, 每个 reload 线程记住自己处理到的值, 同一进程中的多个 server 都能看到
+static HANGUPS: AtomicU64 = AtomicU64::new(0);
+
+#[cfg(unix)]
+const SIG_ERR: usize = usize::MAX;
+
+#[cfg(unix)]
+unsafe extern "C" {
+    // 返回原来的处理方式, 失败时为 SIG_ERR(-1)
+    fn signal(signum: i32, handler: usize) -> usize;
+}
+
+/// run 结束时 drop: 恢复安装前的信号处理方式, 等待轮询线程退出
+pub(crate) struct SignalGuard {
+    previous: Vec<(i32, usize)>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Drop for SignalGuard {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        for (signum, previous) in self.previous.drain(..) {
+            unsafe {
+                signal(signum, previous);
+            }
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn on_signals(handle: ShutdownHandle) -> SignalGuard {
+    const SIGINT: i32 = 2;
+    const SIGTERM: i32 = 15;
+    extern "C" fn handler(_: i32) {
+        SIGNALED.store(true, Ordering::SeqCst);
+    }
+    // 同一进程中再次 run 时不受上一次收到的信号影响
+    SIGNALED.store(false, Ordering::SeqCst);
+    let previous = install(&[SIGINT, SIGTERM], handler);
+    // 由信号或 shutdown_handle 发起关闭后都会退出
+    let thread = thread::spawn(move || {
+        while !handle.is_shutdown() {
+            if SIGNALED.load(Ordering::SeqCst) {
+                handle.shutdown();
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    });
+    SignalGuard {
+        previous,
+        thread: Some(thread),
+    }
+}
+
+// 返回安装成功的信号与原来的处理方式
+#[cfg(unix)]
+fn install(signums: &[i32], handler: extern "C" fn(i32)) -> Vec<(i32, usize)> {
+    let handler_address = handler as usize;
+    signums
+        .iter()
+        .filter_map(|&signum| {
+            let previous = unsafe { signal(signum, handler_address) };
+            (previous != SIG_ERR).then_some((signum, previous))
+        })
+        .collect()
+}
+
+// 只计数, 由 reload 线程轮询 hangups 后重新加载
+#[cfg(unix)]
+pub(crate) fn on_hangup() -> SignalGuard {
+    const SIGHUP: i32 = 1;
+    extern "C" fn handler(_: i32) {
+        HANGUPS.fetch_add(1, Ordering::SeqCst);
+    }
+    SignalGuard {
+        previous: install(&[SIGHUP], handler),
+        thread: None,
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn on_hangup() -> SignalGuard {
+    log::warn("reload on SIGHUP is only supported on unix, use HttpServer::watch_config");
+    SignalGuard {
+        previous: Vec::new(),
+        thread: None,
+    }
+}
+
+pub(crate) fn hangups() -> u64 {
+    HANGUPS.load(Ordering::SeqCst)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn on_signals(_handle: ShutdownHandle) -> SignalGuard {
+    log::warn("shutdown on signals is only supported on unix, use HttpServer::shutdown_handle");
+    SignalGuard {
+        previous: Vec::new(),
+        thread: None,
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signal_guard_restores_previous_handlers() {
+        const SIGTERM: i32 = 15;
+        const SIG_IGN: usize = 1;
+        let original = unsafe { signal(SIGTERM, SIG_IGN) };
+        let handle = ShutdownHandle::new(Arc::default());
+        let guard = on_signals(handle.clone());
+        handle.shutdown();
+        drop(guard);
+        // guard 恢复的是安装前的 SIG_IGN
+        let restored = unsafe { signal(SIGTERM, original) };
+        assert_eq!(restored, SIG_IGN);
+    }
+
+    #[test]
+    fn hangup_is_counted_while_installed() {
+        unsafe extern "C" {
+            fn raise(signum: i32) -> i32;
+        }
+        const SIGHUP: i32 = 1;
+        let guard = on_hangup();
+        let before = hangups();
+        unsafe {
+            raise(SIGHUP);
+        }
+        assert!(hangups() > before);
+        drop(guard);
+    }
+}
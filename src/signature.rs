@@ -0,0 +1,186 @@
+use crate::hash::{constant_time_eq, hmac_sha256, to_hex, Sha256};
+use crate::{HttpRequest, HttpResponse, Middleware};
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// 机器间调用的 HMAC-SHA256 请求签名校验
+// 客户端发送:
+//   X-Signature-Timestamp: <unix 秒>
+//   X-Signature: sha256=<hex(HMAC(secret, 待签名串))>
+// 待签名串: "<timestamp>\n<METHOD>\n<path?query>\n<hex(sha256(body))>"
+// server.add_middleware(RequestSignature::new(b"secret").middleware().path("/hooks/**".into()));
+
+/// 签名校验的配置, middleware() 之后由中间件持有
+pub struct RequestSignature {
+    secret: Vec<u8>,
+    window_secs: u64,
+}
+
+impl RequestSignature {
+    /// 时间窗口默认 300 秒
+    pub fn new(secret: &[u8]) -> RequestSignature {
+        RequestSignature {
+            secret: secret.to_vec(),
+            window_secs: 300,
+        }
+    }
+    /// 允许的时钟偏差, 超出窗口的请求直接拒绝
+    pub fn window_secs(mut self, window_secs: u64) -> Self {
+        self.window_secs = window_secs;
+        self
+    }
+
+    /// 签名缺失、错误、超出时间窗口或被重放时回复 401
+    pub fn middleware(self) -> Middleware {
+        let verifier = Verifier {
+            config: self,
+            seen: Mutex::new(HashMap::new()),
+        };
+        Middleware::new(move |chain, ctx| {
+            if verifier.verify(&ctx.request) {
+                chain.next(ctx);
+            } else {
+                ctx.set_response(HttpResponse::new(401));
+            }
+        })
+    }
+}
+
+struct Verifier {
+    config: RequestSignature,
+    // 窗口内已经用过的签名, 防止原样重放
+    seen: Mutex<HashMap<String, u64>>,
+}
+
+impl Verifier {
+    fn verify(&self, request: &HttpRequest) -> bool {
+        let (Some(timestamp), Some(signature)) = (
+            request.header("X-Signature-Timestamp"),
+            request.header("X-Signature"),
+        ) else {
+            return false;
+        };
+        let Ok(sent_at) = timestamp.trim().parse::<u64>() else {
+            return false;
+        };
+        let signature = signature.trim();
+        let signature = signature.strip_prefix("sha256=").unwrap_or(signature);
+        let config = &self.config;
+        if config.secret.is_empty() {
+            crate::log::error("signature middleware used without a secret");
+            return false;
+        }
+        let now = now_secs();
+        if now.abs_diff(sent_at) > config.window_secs {
+            return false;
+        }
+        let to_sign = match string_to_sign(timestamp.trim(), request) {
+            Ok(to_sign) => to_sign,
+            Err(e) => {
+                crate::log::error(&format!("read request body for signature failed: {}", e));
+                return false;
+            }
+        };
+        let expected = to_hex(&hmac_sha256(&config.secret, to_sign.as_bytes()));
+        if !constant_time_eq(expected.as_bytes(), signature.to_ascii_lowercase().as_bytes()) {
+            return false;
+        }
+        let window_secs = config.window_secs;
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, at| now.abs_diff(*at) <= window_secs);
+        seen.insert(expected, sent_at).is_none()
+    }
+}
+
+/// 落盘的请求体分段读出参与签名, 读取失败时返回错误
+pub fn string_to_sign(timestamp: &str, request: &HttpRequest) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut reader = request.body_reader()?;
+    let mut buf = [0u8; 8192];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => hasher.update(&buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    let target = if request.query_string.is_empty() {
+        request.path.clone()
+    } else {
+        format!("{}?{}", request.path, request.query_string)
+    };
+    Ok(format!(
+        "{}\n{:?}\n{}\n{}",
+        timestamp,
+        request.method,
+        target,
+        to_hex(&hasher.finish())
+    ))
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HttpMethod, HttpServer, Service};
+
+    fn signed(path: &str, secret: &[u8], body: &str, timestamp: &str) -> HttpRequest {
+        let head = |signature: &str| {
+            format!(
+                "POST {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\nX-Signature-Timestamp: {}\r\nX-Signature: sha256={}\r\n\r\n{}",
+                path,
+                body.len(),
+                timestamp,
+                signature,
+                body
+            )
+        };
+        let unsigned = head("");
+        let request = HttpRequest::parse(&mut unsigned.as_bytes(), "127.0.0.1:50000".into()).unwrap();
+        let signature = to_hex(&hmac_sha256(secret, string_to_sign(timestamp, &request).unwrap().as_bytes()));
+        HttpRequest::parse(&mut head(&signature).as_bytes(), "127.0.0.1:50000".into()).unwrap()
+    }
+
+    #[test]
+    fn each_middleware_checks_its_own_secret_and_replays() {
+        let mut server = HttpServer::new("127.0.0.1:0".into());
+        server.add_middleware(RequestSignature::new(b"a").middleware().path("/a".into()));
+        server.add_middleware(RequestSignature::new(b"b").middleware().path("/b".into()));
+        for path in ["/a", "/b"] {
+            server.add_handler(HttpMethod::POST, path.into(), |ctx| ctx.set_response(HttpResponse::new(204)));
+        }
+
+        let now = now_secs().to_string();
+        assert_eq!(server.call(signed("/a", b"a", "{}", &now)).status_code, 204);
+        assert_eq!(server.call(signed("/b", b"a", "{}", &now)).status_code, 401);
+        assert_eq!(server.call(signed("/b", b"b", "{\"n\":1}", &now)).status_code, 204);
+        // 同一个签名只能用一次
+        assert_eq!(server.call(signed("/b", b"b", "{\"n\":1}", &now)).status_code, 401);
+        assert_eq!(server.call(signed("/b", b"b", "{}", "1000")).status_code, 401);
+    }
+
+    #[test]
+    fn spooled_body_is_hashed_like_an_in_memory_one() {
+        let body = "x".repeat(20_000);
+        let raw = format!("POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}", body.len(), body);
+        let spool = crate::spool::SpoolConfig {
+            threshold: 1024,
+            dir: std::env::temp_dir(),
+        };
+        let spooled =
+            crate::parse_http_request(&mut raw.as_bytes(), "127.0.0.1:50000".into(), Some(&spool), None, &[]).unwrap();
+        assert!(spooled.body.is_none());
+        let in_memory = HttpRequest::parse(&mut raw.as_bytes(), "127.0.0.1:50000".into()).unwrap();
+        assert!(in_memory.body.is_some());
+        assert_eq!(string_to_sign("1", &spooled).unwrap(), string_to_sign("1", &in_memory).unwrap());
+    }
+}
@@ -0,0 +1,103 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+// 请求体超过阈值后写入临时文件, 内存占用不随上传大小增长
+
+#[derive(Debug, Clone)]
+pub struct SpoolConfig {
+    pub threshold: u64,
+    pub dir: PathBuf,
+}
+
+// 落盘的请求体, 最后一个引用释放时删除临时文件
+#[derive(Debug)]
+pub struct SpooledFile {
+    pub path: PathBuf,
+    pub len: u64,
+}
+
+impl Drop for SpooledFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+pub enum Body {
+    Memory(Vec<u8>),
+    Spooled(Arc<SpooledFile>),
+}
+
+// 先写内存, 累计超过阈值时把已有内容连同后续数据一起转存到文件
+pub struct BodyBuffer<'a> {
+    config: Option<&'a SpoolConfig>,
+    memory: Vec<u8>,
+    file: Option<(File, SpooledFile)>,
+}
+
+impl<'a> BodyBuffer<'a> {
+    pub fn new(config: Option<&'a SpoolConfig>) -> BodyBuffer<'a> {
+        BodyBuffer {
+            config,
+            memory: Vec::new(),
+            file: None,
+        }
+    }
+
+    pub fn finish(self) -> io::Result<Body> {
+        match self.file {
+            Some((mut file, spooled)) => {
+                file.flush()?;
+                Ok(Body::Spooled(Arc::new(spooled)))
+            }
+            None => Ok(Body::Memory(self.memory)),
+        }
+    }
+
+    fn spool(config: &SpoolConfig) -> io::Result<(File, SpooledFile)> {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        let name = format!(
+            "rustbook-httpserver-{}-{}.body",
+            process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = config.dir.join(name);
+        let file = OpenOptions::new().write(true).create_new(true).open(&path)?;
+        Ok((file, SpooledFile { path, len: 0 }))
+    }
+}
+
+impl Write for BodyBuffer<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.file.is_none()
+            && let Some(config) = self.config
+            && (self.memory.len() + buf.len()) as u64 > config.threshold
+        {
+            let (mut file, mut spooled) = BodyBuffer::spool(config)?;
+            file.write_all(&self.memory)?;
+            spooled.len = self.memory.len() as u64;
+            self.memory = Vec::new();
+            self.file = Some((file, spooled));
+        }
+        match self.file.as_mut() {
+            Some((file, spooled)) => {
+                let n = file.write(buf)?;
+                spooled.len += n as u64;
+                Ok(n)
+            }
+            None => {
+                self.memory.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file.as_mut() {
+            Some((file, _)) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
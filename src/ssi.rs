@@ -0,0 +1,105 @@
+use crate::static_dir::{escape_html, StaticDir};
+use crate::{format_now, http_date, log, HttpRequest, HttpResponse};
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+// 服务端包含(SSI), 只实现 include 与 echo 两个指令, 见 StaticDir::with_ssi
+// 指令出错时与 Apache 一样在原位置输出错误提示, 页面其余部分照常返回
+
+const ERROR_MESSAGE: &str = "[an error occurred while processing this directive]";
+// 防止互相引用的文件无限展开
+const MAX_DEPTH: usize = 8;
+
+pub(crate) fn is_ssi_file(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("shtml"))
+}
+
+// 结果随引用的文件与变量变化, 不设置 ETag 与 Last-Modified
+pub(crate) fn response(dir: &StaticDir, request: &HttpRequest, path: &Path) -> HttpResponse {
+    let mut out = String::new();
+    match process(dir, request, path, 0, &mut out) {
+        Ok(()) => HttpResponse::bytes("text/html; charset=utf-8".into(), out.into_bytes()),
+        Err(e) => {
+            log::error(&format!("ssi failed: {} {:?}", e, path));
+            HttpResponse::new(500)
+        }
+    }
+}
+
+fn process(dir: &StaticDir, request: &HttpRequest, path: &Path, depth: usize, out: &mut String) -> std::io::Result<()> {
+    let content = fs::read_to_string(path)?;
+    let mut rest = content.as_str();
+    while let Some(start) = rest.find("<!--#") {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(len) = rest.find("-->") else {
+            break;
+        };
+        let directive = rest[5..len].trim();
+        rest = &rest[len + 3..];
+        if execute(dir, request, path, depth, directive, out).is_none() {
+            log::warn(&format!("ssi directive failed: <!--#{} --> in {:?}", directive, path));
+            out.push_str(ERROR_MESSAGE);
+        }
+    }
+    out.push_str(rest);
+    Ok(())
+}
+
+fn execute(dir: &StaticDir, request: &HttpRequest, path: &Path, depth: usize, directive: &str, out: &mut String) -> Option<()> {
+    let (command, attribute) = directive.split_once(char::is_whitespace)?;
+    let (name, value) = attribute.trim().split_once('=')?;
+    let value = value.trim();
+    let value = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))?;
+    match (command, name.trim()) {
+        ("include", kind @ ("virtual" | "file")) => {
+            if depth >= MAX_DEPTH {
+                return None;
+            }
+            // virtual 以 / 开头时为挂载点下的 url, 否则与 file 一样相对于当前文件所在目录
+            let target = if value.starts_with('/') {
+                let target = value.strip_prefix(dir.prefix.as_str()).filter(|_| kind == "virtual")?;
+                if !target.is_empty() && !target.starts_with('/') {
+                    return None;
+                }
+                dir.resolve(target)?
+            } else if dir.is_hidden(value) {
+                return None;
+            } else {
+                path.parent()?.join(value)
+            };
+            if !target.is_file() || !dir.contains(&target) {
+                return None;
+            }
+            if is_ssi_file(&target) {
+                process(dir, request, &target, depth + 1, out).ok()
+            } else {
+                out.push_str(&fs::read_to_string(&target).ok()?);
+                Some(())
+            }
+        }
+        ("echo", "var") => {
+            out.push_str(&escape_html(&variable(request, path, value)));
+            Some(())
+        }
+        _ => None,
+    }
+}
+
+fn variable(request: &HttpRequest, path: &Path, name: &str) -> String {
+    let value = match name {
+        "DOCUMENT_NAME" => path.file_name().map(|name| name.to_string_lossy().into_owned()),
+        "DOCUMENT_URI" => Some(request.path.clone()),
+        "QUERY_STRING" => Some(request.query_string.clone()),
+        "REMOTE_ADDR" => Some(request.remote_addr.clone()),
+        "DATE_LOCAL" => Some(format_now()),
+        "DATE_GMT" => Some(http_date::format(SystemTime::now())),
+        "LAST_MODIFIED" => fs::metadata(path).and_then(|m| m.modified()).ok().map(http_date::format),
+        _ => None,
+    };
+    value.unwrap_or_else(|| "(none)".to_string())
+}
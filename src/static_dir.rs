@@ -0,0 +1,281 @@
+use crate::negotiation::preferred_encoding;
+use crate::{format_datetime, log, offset8, ssi, url, EtagPolicy, Error, HttpRequest, HttpResponse};
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// serve_dir 挂载的目录: 请求路径解码后映射到 root 下的文件, 不能通过 .. 或符号链接离开 root
+
+/// HttpServer::serve_dir 返回, 用于调整单个挂载点
+#[derive(Debug, Clone)]
+pub struct StaticDir {
+    pub(crate) prefix: String,
+    root: String,
+    listing: bool,
+    ssi: bool,
+    precompressed: bool,
+    dotfiles: bool,
+    pub(crate) etag: Option<EtagPolicy>,
+    // run 启动时检查后填充
+    canonical_root: Option<PathBuf>,
+    missing: bool,
+}
+
+impl StaticDir {
+    pub(crate) fn new(prefix: &str, root: &str) -> StaticDir {
+        StaticDir {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            root: root.to_string(),
+            listing: false,
+            ssi: false,
+            precompressed: false,
+            dotfiles: false,
+            etag: None,
+            canonical_root: None,
+            missing: false,
+        }
+    }
+    /// 目录中没有 index.html 时返回自动生成的文件列表(名称、大小、修改时间), 默认关闭, 此时为 404
+    ///
+    /// 列表中不含 . 开头的隐藏文件
+    pub fn with_listing(&mut self, enabled: bool) -> &mut Self {
+        self.listing = enabled;
+        self
+    }
+
+    /// 以 SSI 处理 .shtml 文件, 支持 <!--#include virtual="..." -->、<!--#include file="..." --> 与 <!--#echo var="..." -->, 默认关闭
+    ///
+    /// include 只能引用这个挂载点之内的文件, 被引用的 .shtml 同样会被处理
+    pub fn with_ssi(&mut self, enabled: bool) -> &mut Self {
+        self.ssi = enabled;
+        self
+    }
+
+    /// 客户端接受 br 或 gzip 且存在 foo.js.br、foo.js.gz 时发送压缩好的文件, Content-Type 仍按 foo.js, 默认关闭
+    pub fn with_precompressed(&mut self, enabled: bool) -> &mut Self {
+        self.precompressed = enabled;
+        self
+    }
+
+    /// 允许访问 . 开头的文件与目录, 如 /.well-known/; 默认关闭, 此时 /.env、/.git/config 等都是 404
+    pub fn with_dotfiles(&mut self, enabled: bool) -> &mut Self {
+        self.dotfiles = enabled;
+        self
+    }
+
+    /// 这个挂载点的文件使用的 ETag 策略, 优先于 HttpServer::etag_policy; 未设置时按 etag_policy
+    pub fn with_etag(&mut self, policy: EtagPolicy) -> &mut Self {
+        self.etag = Some(policy);
+        self
+    }
+
+    pub(crate) fn check(&mut self, missing_as_empty: bool) -> Result<(), Error> {
+        match check_root("static root", &self.root, missing_as_empty)? {
+            Some(root) => self.canonical_root = Some(root),
+            None => self.missing = true,
+        }
+        Ok(())
+    }
+
+    // target 为去掉前缀后的路径, 空或以 / 开头
+    pub(crate) fn response(&self, request: &HttpRequest, target: &str) -> HttpResponse {
+        if self.missing {
+            return HttpResponse::new(404);
+        }
+        let Some(path) = self.resolve(target) else {
+            return HttpResponse::new(404);
+        };
+        if !path.is_dir() {
+            if self.ssi && ssi::is_ssi_file(&path) && path.is_file() {
+                return ssi::response(self, request, &path);
+            }
+            return self.file_response(request, &path);
+        }
+        let index = path.join("index.html");
+        if self.listing && !index.is_file() {
+            return listing(request, target, &path).unwrap_or_else(|| HttpResponse::new(404));
+        }
+        self.file_response(request, &index)
+    }
+
+    fn file_response(&self, request: &HttpRequest, path: &Path) -> HttpResponse {
+        let response = HttpResponse::file(path.to_string_lossy().into_owned());
+        if !self.precompressed {
+            return response;
+        }
+        // 服务端偏好 br, 它通常比 gzip 小
+        let variants = PRECOMPRESSED
+            .iter()
+            .map(|(coding, extension)| (*coding, append_extension(path, extension)))
+            .filter(|(_, variant)| variant.is_file() && self.contains(variant))
+            .collect::<Vec<_>>();
+        if variants.is_empty() {
+            return response;
+        }
+        // 同一个 url 的内容随 Accept-Encoding 变化
+        let response = response.add_vary("Accept-Encoding");
+        let mut available = variants.iter().map(|(coding, _)| *coding).collect::<Vec<&str>>();
+        available.push("identity");
+        let accept_encoding = request.header("Accept-Encoding").unwrap_or_default();
+        let Some((coding, variant)) = preferred_encoding(accept_encoding, &available)
+            .and_then(|coding| variants.into_iter().find(|(c, _)| *c == coding))
+        else {
+            return response;
+        };
+        let mut precompressed = response.add_header("Content-Encoding".into(), coding.into());
+        precompressed.file = Some(variant.to_string_lossy().into_owned());
+        precompressed
+    }
+
+    // 解析符号链接后位于 root 之内
+    pub(crate) fn contains(&self, path: &Path) -> bool {
+        let root = self.canonical_root.clone().map_or_else(|| fs::canonicalize(&self.root), Ok);
+        match (root, fs::canonicalize(path)) {
+            (Ok(root), Ok(path)) => path.starts_with(root),
+            _ => false,
+        }
+    }
+
+    // 没有开启 with_dotfiles 时, 含有 . 开头的段(. 与 .. 除外)的路径不能访问
+    pub(crate) fn is_hidden(&self, path: &str) -> bool {
+        !self.dotfiles
+            && path
+                .split(['/', '\\'])
+                .any(|segment| segment.starts_with('.') && segment != "." && segment != "..")
+    }
+
+    // 解码后的路径不能含有 .. 与 \0, 解析符号链接后还要位于 root 之内; 文件不存在时返回拼接的路径, 由 open_body 回复 404
+    pub(crate) fn resolve(&self, target: &str) -> Option<PathBuf> {
+        let target = url::percent_decode(target, false);
+        if self.is_hidden(&target) {
+            return None;
+        }
+        let mut relative = PathBuf::new();
+        for segment in target.split(['/', '\\']) {
+            match segment {
+                "" | "." => {}
+                ".." => return None,
+                _ if segment.contains('\0') => return None,
+                _ => relative.push(segment),
+            }
+        }
+        let path = Path::new(&self.root).join(relative);
+        let root = self.canonical_root.clone().map_or_else(|| fs::canonicalize(&self.root), Ok);
+        match (root, fs::canonicalize(&path)) {
+            (Ok(root), Ok(canonical)) if !canonical.starts_with(&root) => None,
+            (Ok(_), Ok(canonical)) => Some(canonical),
+            _ => Some(path),
+        }
+    }
+}
+
+// (Content-Encoding, 文件扩展名), 按服务端偏好排列
+const PRECOMPRESSED: [(&str, &str); 2] = [("br", "br"), ("gzip", "gz")];
+
+fn append_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".");
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+// 发送预压缩文件时 Content-Type 按去掉 .br、.gz 后的原文件名确定
+pub(crate) fn original_path<'a>(response: &HttpResponse, path: &'a str) -> &'a str {
+    let Some(coding) = response.header("Content-Encoding") else {
+        return path;
+    };
+    PRECOMPRESSED
+        .iter()
+        .find(|(c, _)| c.eq_ignore_ascii_case(coding))
+        .and_then(|(_, extension)| path.strip_suffix(extension)?.strip_suffix('.'))
+        .unwrap_or(path)
+}
+
+// 目录必须存在且可读, 返回规范化后的路径; missing_as_empty 时不存在的目录只记录一次警告, 返回 None
+pub(crate) fn check_root(kind: &str, root: &str, missing_as_empty: bool) -> Result<Option<PathBuf>, Error> {
+    let checked = fs::canonicalize(root).and_then(|canonical| fs::read_dir(&canonical).map(|_| canonical));
+    match checked {
+        Ok(canonical) => Ok(Some(canonical)),
+        Err(e) if missing_as_empty && e.kind() == io::ErrorKind::NotFound => {
+            log::warn(&format!("{} {} does not exist, serving it as empty", kind, root));
+            Ok(None)
+        }
+        Err(e) => Err(Error::Config(format!("{} {}: {}", kind, root, e))),
+    }
+}
+
+fn listing(request: &HttpRequest, target: &str, dir: &Path) -> Option<HttpResponse> {
+    let mut entries = fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let metadata = entry.metadata().ok()?;
+            (!name.starts_with('.')).then_some((name, metadata))
+        })
+        .collect::<Vec<_>>();
+    // 目录在前, 再按名称排序
+    entries.sort_by(|(a, a_meta), (b, b_meta)| b_meta.is_dir().cmp(&a_meta.is_dir()).then_with(|| a.cmp(b)));
+
+    // 链接使用绝对路径, 请求路径不以 / 结尾时也正确
+    let base = request.path.trim_end_matches('/');
+    let title = escape_html(&url::percent_decode(&request.path, false));
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {0}</title></head>\n<body>\n<h1>Index of {0}</h1>\n<table>\n",
+        title
+    );
+    let _ = writeln!(html, "<tr><th>Name</th><th>Size</th><th>Last modified</th></tr>");
+    if !target.trim_matches('/').is_empty() {
+        let parent = base.rsplit_once('/').map_or("", |(parent, _)| parent);
+        let _ = writeln!(html, "<tr><td><a href=\"{}/\">../</a></td><td></td><td></td></tr>", escape_html(parent));
+    }
+    for (name, metadata) in entries.iter() {
+        let slash = if metadata.is_dir() { "/" } else { "" };
+        let size = if metadata.is_dir() { "-".to_string() } else { metadata.len().to_string() };
+        let modified = metadata.modified().map_or(String::new(), |time| format_datetime(time, offset8()));
+        let _ = writeln!(
+            html,
+            "<tr><td><a href=\"{}/{}{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>",
+            escape_html(base),
+            percent_encode(name),
+            slash,
+            escape_html(name),
+            slash,
+            size,
+            modified
+        );
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    Some(HttpResponse::bytes("text/html; charset=utf-8".into(), html.into_bytes()))
+}
+
+pub(crate) fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// 文件名作为一个路径段编码
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            let _ = write!(encoded, "%{:02X}", byte);
+        }
+    }
+    encoded
+}
@@ -0,0 +1,278 @@
+use crate::Error;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// 固定数量的工作线程, 任务通过 channel 分发, 见 Rust 程序设计语言第 21 章
+
+pub type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// 关闭时如何处理还在排队、尚未开始执行的任务
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PendingJobs {
+    /// 执行完再退出, drop 时的行为
+    Complete,
+    /// 直接丢弃
+    Cancel,
+    /// 不执行, 由 shutdown 返回给调用方
+    Return,
+}
+
+pub struct ThreadPool {
+    workers: Vec<Worker>,
+    // 为 None 时在调用 execute 的线程上直接执行
+    sender: Option<mpsc::Sender<Job>>,
+    shared: Arc<Shared>,
+    shutdown_timeout: Option<Duration>,
+}
+
+// 取消后工作线程把取到的任务放进 pending 而不是执行
+struct Shared {
+    cancelled: AtomicBool,
+    pending: Mutex<Vec<Job>>,
+    // 已提交还没有被工作线程取走的任务数与正在执行的任务数
+    queued: AtomicUsize,
+    active: AtomicUsize,
+}
+
+impl Shared {
+    fn new() -> Arc<Shared> {
+        Arc::new(Shared {
+            cancelled: AtomicBool::new(false),
+            pending: Mutex::new(Vec::new()),
+            queued: AtomicUsize::new(0),
+            active: AtomicUsize::new(0),
+        })
+    }
+
+    fn run(&self, job: Job) {
+        self.active.fetch_add(1, Ordering::SeqCst);
+        job();
+        self.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl ThreadPool {
+    /// size 为工作线程数, 为 0 时 panic
+    pub fn new(size: usize) -> ThreadPool {
+        assert!(size > 0);
+        let (sender, receiver) = mpsc::channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let shared = Shared::new();
+        let workers = (0..size)
+            .map(|id| Worker::new(id, Arc::clone(&receiver), Arc::clone(&shared)))
+            .collect();
+        ThreadPool {
+            workers,
+            sender: Some(sender),
+            shared,
+            shutdown_timeout: None,
+        }
+    }
+
+    /// 不创建线程, execute 在调用处同步执行任务, 用于测试中得到确定的执行顺序或不能创建线程的环境
+    pub fn new_current_thread() -> ThreadPool {
+        ThreadPool {
+            workers: Vec::new(),
+            sender: None,
+            shared: Shared::new(),
+            shutdown_timeout: None,
+        }
+    }
+
+    /// 关闭时最多等待工作线程 timeout, 之后不再等待仍在执行的任务(线程被分离, 任务继续在后台跑完)
+    ///
+    /// 默认一直等待, 一个卡住的任务会让关闭永远无法完成
+    pub fn shutdown_timeout(&mut self, timeout: Duration) {
+        self.shutdown_timeout = Some(timeout);
+    }
+
+    /// 所有工作线程都已退出时返回 Error::PoolClosed
+    pub fn execute<F>(&self, f: F) -> Result<(), Error>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        match self.sender.as_ref() {
+            Some(sender) => {
+                self.shared.queued.fetch_add(1, Ordering::SeqCst);
+                sender.send(Box::new(f)).map_err(|_| {
+                    self.shared.queued.fetch_sub(1, Ordering::SeqCst);
+                    Error::PoolClosed
+                })
+            }
+            None => {
+                self.shared.run(Box::new(f));
+                Ok(())
+            }
+        }
+    }
+
+    /// 排队中、还没有开始执行的任务数
+    pub fn queued_jobs(&self) -> usize {
+        self.shared.queued.load(Ordering::SeqCst)
+    }
+
+    /// 正在执行的任务数
+    pub fn active_jobs(&self) -> usize {
+        self.shared.active.load(Ordering::SeqCst)
+    }
+
+    /// 停止接受任务并等待工作线程退出, 排队中的任务按 pending 处理; 只有 PendingJobs::Return 时返回非空
+    pub fn shutdown(mut self, pending: PendingJobs) -> Vec<Job> {
+        if pending != PendingJobs::Complete {
+            self.shared.cancelled.store(true, Ordering::SeqCst);
+        }
+        self.stop();
+        let jobs = std::mem::take(&mut *self.shared.pending.lock().unwrap());
+        match pending {
+            PendingJobs::Return => jobs,
+            _ => Vec::new(),
+        }
+    }
+
+    // 关闭 channel, 工作线程取完队列中的任务后退出
+    fn stop(&mut self) {
+        drop(self.sender.take());
+        let deadline = self.shutdown_timeout.map(|timeout| Instant::now() + timeout);
+        for worker in self.workers.drain(..) {
+            if let Some(deadline) = deadline {
+                while !worker.thread.is_finished() && Instant::now() < deadline {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                if !worker.thread.is_finished() {
+                    crate::log::warn(&format!("worker {} still busy after shutdown timeout, detached", worker.id));
+                    continue;
+                }
+            }
+            if worker.thread.join().is_err() {
+                crate::log::error(&format!("worker {} panicked", worker.id));
+            }
+        }
+    }
+}
+
+// 等待已排队的任务执行完, 受 shutdown_timeout 限制
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+struct Worker {
+    id: usize,
+    thread: thread::JoinHandle<()>,
+}
+
+impl Worker {
+    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>, shared: Arc<Shared>) -> Worker {
+        let thread = thread::spawn(move || {
+            loop {
+                // 取到任务后立即释放锁, 其他线程才能继续取
+                let message = receiver.lock().unwrap().recv();
+                if message.is_ok() {
+                    shared.queued.fetch_sub(1, Ordering::SeqCst);
+                }
+                match message {
+                    Ok(job) if shared.cancelled.load(Ordering::SeqCst) => shared.pending.lock().unwrap().push(job),
+                    Ok(job) => shared.run(job),
+                    Err(_) => break,
+                }
+            }
+        });
+        Worker { id, thread }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_queued_and_active_jobs() {
+        let pool = ThreadPool::new(1);
+        let (release, blocked) = mpsc::channel::<()>();
+        let (started, wait_started) = mpsc::channel();
+        pool.execute(move || {
+            started.send(()).unwrap();
+            blocked.recv().unwrap();
+        })
+        .unwrap();
+        pool.execute(|| {}).unwrap();
+        wait_started.recv().unwrap();
+        assert_eq!((pool.queued_jobs(), pool.active_jobs()), (1, 1));
+        release.send(()).unwrap();
+        pool.shutdown(PendingJobs::Complete);
+    }
+
+    // 一个工作线程被第一个任务卡住, 之后的 count 个任务都在排队; 返回放行第一个任务的 sender 与计数
+    fn blocked_pool(count: usize) -> (ThreadPool, mpsc::Sender<()>, Arc<AtomicUsize>) {
+        let pool = ThreadPool::new(1);
+        let (release, blocked) = mpsc::channel::<()>();
+        let (started, wait_started) = mpsc::channel();
+        pool.execute(move || {
+            started.send(()).unwrap();
+            let _ = blocked.recv();
+        })
+        .unwrap();
+        wait_started.recv().unwrap();
+        let ran = Arc::new(AtomicUsize::new(0));
+        for _ in 0..count {
+            let ran = Arc::clone(&ran);
+            pool.execute(move || {
+                ran.fetch_add(1, Ordering::SeqCst);
+            })
+            .unwrap();
+        }
+        (pool, release, ran)
+    }
+
+    // shutdown 设置取消标志之后才放行卡住的任务, 排队的任务一定在取消之后被取出
+    fn release_after_cancel(pool: &ThreadPool, release: mpsc::Sender<()>) {
+        let shared = Arc::clone(&pool.shared);
+        thread::spawn(move || {
+            while !shared.cancelled.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(1));
+            }
+            release.send(()).unwrap();
+        });
+    }
+
+    #[test]
+    fn cancel_drops_queued_jobs() {
+        let (pool, release, ran) = blocked_pool(2);
+        release_after_cancel(&pool, release);
+        assert!(pool.shutdown(PendingJobs::Cancel).is_empty());
+        assert_eq!(ran.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn return_hands_back_queued_jobs() {
+        let (pool, release, ran) = blocked_pool(2);
+        release_after_cancel(&pool, release);
+        let jobs = pool.shutdown(PendingJobs::Return);
+        assert_eq!((jobs.len(), ran.load(Ordering::SeqCst)), (2, 0));
+        for job in jobs {
+            job();
+        }
+        assert_eq!(ran.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn shutdown_timeout_detaches_busy_workers() {
+        let (mut pool, release, ran) = blocked_pool(1);
+        pool.shutdown_timeout(Duration::from_millis(50));
+        let shared = Arc::clone(&pool.shared);
+        let started = Instant::now();
+        pool.shutdown(PendingJobs::Complete);
+        assert!(started.elapsed() < Duration::from_secs(2));
+        // 分离的线程仍在执行卡住的任务, 放行后继续跑完排队的任务
+        assert_eq!(shared.active.load(Ordering::SeqCst), 1);
+        release.send(()).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while ran.load(Ordering::SeqCst) == 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(ran.load(Ordering::SeqCst), 1);
+    }
+}
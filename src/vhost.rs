@@ -0,0 +1,51 @@
+use crate::{Context, HttpHandler, StaticDir};
+use std::sync::Arc;
+
+// 同一个端口上的多个站点按 Host 头区分; 路由与中间件仍由所有站点共享,
+// 只有 view 模板、静态目录与错误页这些与站点内容相关的配置可以分开
+
+/// 虚拟主机的配置, 没有设置的部分使用 HttpServer 上的全局配置
+///
+/// 如 server.virtual_host("blog.example.com".into(), VirtualHost::new().view_root("./blog/templates").serve_dir("/static", "./blog/static"))
+#[derive(Default)]
+pub struct VirtualHost {
+    pub(crate) view_root: Option<String>,
+    pub(crate) view_root_missing: bool,
+    // 在全局的 serve_dir 之前匹配
+    pub(crate) static_dirs: Vec<StaticDir>,
+    pub(crate) error_handlers: Vec<(u16, HttpHandler)>,
+    pub(crate) default_error_handler: Option<HttpHandler>,
+}
+
+impl VirtualHost {
+    pub fn new() -> VirtualHost {
+        VirtualHost::default()
+    }
+    /// HttpResponse::view 的模板目录, 代替 HttpServer::view_root
+    pub fn view_root(mut self, root: &str) -> Self {
+        self.view_root = Some(root.to_string());
+        self
+    }
+    /// 同 HttpServer::serve_dir, 只对这个主机生效
+    pub fn serve_dir(mut self, url_prefix: &str, fs_root: &str) -> Self {
+        self.static_dirs.push(StaticDir::new(url_prefix, fs_root));
+        self
+    }
+    /// 同 HttpServer::set_error_handler, 优先于全局的错误页
+    pub fn error_handler<F>(mut self, status_code: u16, handler: F) -> Self
+    where
+        F: Fn(&mut Context) + Send + Sync + 'static,
+    {
+        self.error_handlers.retain(|(status, _)| *status != status_code);
+        self.error_handlers.push((status_code, Arc::new(handler)));
+        self
+    }
+    /// 同 HttpServer::set_default_error_handler, 这个主机上没有单独设置的错误响应都由 handler 生成
+    pub fn default_error_handler<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&mut Context) + Send + Sync + 'static,
+    {
+        self.default_error_handler = Some(Arc::new(handler));
+        self
+    }
+}